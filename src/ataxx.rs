@@ -1,12 +1,12 @@
-use crate::BulletFormat;
+use crate::{BulletFormat, Deduplicate, Pod};
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub struct AtaxxBoard {
     bbs: [u64; 3],
     score: i16,
     result: u8,
-    stm: bool,
+    stm: u8,
     fullm: u16,
     halfm: u8,
     extra: u8,
@@ -14,13 +14,15 @@ pub struct AtaxxBoard {
 
 const _RIGHT_SIZE: () = assert!(std::mem::size_of::<AtaxxBoard>() == 32);
 
+unsafe impl Pod for AtaxxBoard {}
+
 impl AtaxxBoard {
     pub fn bbs(&self) -> [u64; 3] {
         self.bbs
     }
 
     pub fn stm(&self) -> usize {
-        usize::from(self.stm)
+        usize::from(self.stm != 0)
     }
 
     pub fn halfm(&self) -> u8 {
@@ -58,7 +60,7 @@ impl AtaxxBoard {
             bbs,
             score,
             result,
-            stm,
+            stm: u8::from(stm),
             fullm,
             halfm,
             extra: 0,
@@ -71,6 +73,8 @@ impl BulletFormat for AtaxxBoard {
 
     const HEADER_SIZE: usize = 0;
 
+    const FORMAT_ID: u16 = 2;
+
     fn score(&self) -> i16 {
         self.score
     }
@@ -86,6 +90,28 @@ impl BulletFormat for AtaxxBoard {
     fn set_result(&mut self, result: f32) {
         self.result = (2.0 * result) as u8;
     }
+
+    fn swap_bytes(&mut self) {
+        self.bbs = self.bbs.map(u64::swap_bytes);
+        self.score = self.score.swap_bytes();
+        self.fullm = self.fullm.swap_bytes();
+    }
+}
+
+impl Deduplicate for AtaxxBoard {
+    fn without_outcome(&self) -> Self {
+        Self {
+            score: 0,
+            result: 0,
+            fullm: 0,
+            halfm: 0,
+            ..*self
+        }
+    }
+
+    fn set_score(&mut self, score: i16) {
+        self.score = score;
+    }
 }
 
 impl IntoIterator for AtaxxBoard {
@@ -138,7 +164,7 @@ impl std::str::FromStr for AtaxxBoard {
         let stm = stm_str == "o" || stm_str == "b";
 
         let mut board = Self {
-            stm,
+            stm: u8::from(stm),
             ..Default::default()
         };
         board.halfm = parts.get(2).unwrap_or(&"0").parse().unwrap_or(0);
@@ -146,15 +172,29 @@ impl std::str::FromStr for AtaxxBoard {
 
         let mut idx = 0;
 
+        if board_str.split('/').count() > 7 {
+            return Err(String::from("Malformed FEN!"));
+        }
+
         for row in board_str.split('/').rev() {
+            let mut col = 0;
             for ch in row.chars() {
                 match ch {
                     'x' | 'o' | 'r' | 'b' | '-' => {
+                        if col > 6 || idx >= 49 {
+                            return Err(String::from("Malformed FEN!"));
+                        }
+
                         let bb = usize::from(ch == 'o' || ch == 'b') + 2 * usize::from(ch == '-');
                         board.bbs[bb] |= 1 << idx;
                         idx += 1;
+                        col += 1;
+                    }
+                    '1'..='7' => {
+                        let empty = usize::from(ch as u8 - b'1' + 1);
+                        idx += empty;
+                        col += empty;
                     }
-                    '1'..='7' => idx += usize::from(ch as u8 - b'1' + 1),
                     _ => return Err("Unrecognised Character {ch}".to_string()),
                 }
             }
@@ -163,7 +203,6 @@ impl std::str::FromStr for AtaxxBoard {
         board.score = if let Ok(x) = score.parse::<i16>() {
             x
         } else {
-            println!("{s}");
             return Err(String::from("Bad score!"));
         };
 
@@ -171,10 +210,7 @@ impl std::str::FromStr for AtaxxBoard {
             "1.0" | "[1.0]" | "1" => 2,
             "0.5" | "[0.5]" | "1/2" => 1,
             "0.0" | "[0.0]" | "0" => 0,
-            _ => {
-                println!("{s}");
-                return Err(String::from("Bad game result!"));
-            }
+            _ => return Err(String::from("Bad game result!")),
         };
 
         if stm {
@@ -193,7 +229,7 @@ impl std::fmt::Display for AtaxxBoard {
         let mut score = self.score;
         let mut result = self.result;
 
-        if self.stm {
+        if self.stm() == 1 {
             bbs.swap(0, 1);
             score = -score;
             result = 2 - result;
@@ -246,6 +282,7 @@ impl std::fmt::Display for AtaxxBoard {
 #[cfg(test)]
 mod test {
     use super::AtaxxBoard;
+    use crate::Deduplicate;
 
     #[test]
     fn parse() {
@@ -283,4 +320,30 @@ mod test {
             assert_eq!(board.to_string(), fen);
         }
     }
+
+    #[test]
+    fn malformed() {
+        let fens = [
+            "7/7/7/7/7/7/7/x x 0 1 | 0 | 0.5",
+            "7x/7/7/7/7/7/7 x 0 1 | 0 | 0.5",
+            "77777777x/7/7/7/7/7/7 x 0 1 | 0 | 0.5",
+        ];
+
+        for fen in fens {
+            assert!(fen.parse::<AtaxxBoard>().is_err(), "{fen}");
+        }
+    }
+
+    #[test]
+    fn dedup_key() {
+        let board: AtaxxBoard = "6o/7/5x1/3xxxx/4oxx/4ooo/3x1oo o 1 14 | 120 | 0.0"
+            .parse()
+            .unwrap();
+        let later: AtaxxBoard = "6o/7/5x1/3xxxx/4oxx/4ooo/3x1oo o 5 40 | -30 | 1.0"
+            .parse()
+            .unwrap();
+
+        assert_ne!(board, later);
+        assert_eq!(board.without_outcome(), later.without_outcome());
+    }
 }
@@ -0,0 +1,300 @@
+use std::{fmt, io, path::Path};
+
+use crate::{util, BulletFormat, DataLoader, Progress};
+
+/// Empirical outcome of the positions with scores in a range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoreBucket {
+    /// Lowest score in the bucket.
+    pub start: i32,
+    pub count: usize,
+    /// Mean result, counting draws as half a win.
+    pub win_rate: f64,
+    /// Win rate predicted from the score by the fitted scale.
+    pub expected: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Consistent,
+    /// Scores correlate too weakly with results to be trusted.
+    Weak,
+    /// Higher scores lead to worse results, likely due to a sign error.
+    Inverted,
+}
+
+/// Consistency of the scores in a dataset with its results.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Audit {
+    pub buckets: Vec<ScoreBucket>,
+    /// Scale minimising the log-loss of `sigmoid(score, scale)` against
+    /// the results, as used by [`BulletFormat::blended_result`].
+    pub scale: f32,
+    /// Pearson correlation between score and result.
+    pub correlation: f64,
+    pub verdict: Verdict,
+}
+
+impl fmt::Display for Audit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Verdict     : {:?}", self.verdict)?;
+        writeln!(f, "Correlation : {:.4}", self.correlation)?;
+        writeln!(
+            f,
+            "Scale       : {:.6} (1 / {:.1})",
+            self.scale,
+            1.0 / self.scale
+        )?;
+        writeln!(f, "Score Buckets (Win Rate / Expected):")?;
+
+        for bucket in &self.buckets {
+            writeln!(
+                f,
+                "  {:>6} : {:.3} / {:.3} ({})",
+                bucket.start, bucket.win_rate, bucket.expected, bucket.count
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Audits a file, bucketing positions by score with the given width. It is
+/// flagged as inverted if the correlation between score and result is below
+/// `-min_correlation`, or as weak if it is otherwise below `min_correlation`.
+pub fn audit_file<T: BulletFormat>(
+    path: impl AsRef<Path>,
+    bucket_width: i32,
+    min_correlation: f64,
+    progress: &mut impl Progress,
+) -> io::Result<Audit> {
+    assert!(bucket_width > 0, "Bucket width must be positive!");
+
+    let results = ScoreResults::gather(DataLoader::<T>::new(path, 512)?, progress)?;
+
+    let scale = results.fit_scale();
+    let correlation = results.correlation();
+
+    let verdict = if results.count > 0 && correlation < -min_correlation {
+        Verdict::Inverted
+    } else if results.count == 0 || correlation < min_correlation {
+        Verdict::Weak
+    } else {
+        Verdict::Consistent
+    };
+
+    let mut buckets = Vec::<ScoreBucket>::new();
+    let mut expected_sum = 0.0;
+
+    for (score, count, result_sum) in results.iter() {
+        let start = score.div_euclid(bucket_width) * bucket_width;
+
+        if buckets.last().is_none_or(|bucket| bucket.start != start) {
+            if let Some(bucket) = buckets.last_mut() {
+                bucket.win_rate /= bucket.count as f64;
+                bucket.expected = expected_sum / bucket.count as f64;
+            }
+
+            expected_sum = 0.0;
+            buckets.push(ScoreBucket {
+                start,
+                count: 0,
+                win_rate: 0.0,
+                expected: 0.0,
+            });
+        }
+
+        let bucket = buckets.last_mut().unwrap();
+        bucket.count += count;
+        bucket.win_rate += result_sum;
+        expected_sum += count as f64 * f64::from(util::sigmoid(score as f32, scale));
+    }
+
+    if let Some(bucket) = buckets.last_mut() {
+        bucket.win_rate /= bucket.count as f64;
+        bucket.expected = expected_sum / bucket.count as f64;
+    }
+
+    Ok(Audit {
+        buckets,
+        scale,
+        correlation,
+        verdict,
+    })
+}
+
+/// Fits the scale for [`BulletFormat::blended_result`] to a dataset, by
+/// minimising the log-loss of `sigmoid(score, scale)` against the results.
+pub fn fit_scale<T: BulletFormat>(
+    loader: DataLoader<T>,
+    progress: &mut impl Progress,
+) -> io::Result<f32> {
+    Ok(ScoreResults::gather(loader, progress)?.fit_scale())
+}
+
+/// Sum of results for each possible score.
+struct ScoreResults {
+    counts: Vec<usize>,
+    result_sums: Vec<f64>,
+    count: usize,
+    result_sq_sum: f64,
+}
+
+impl Default for ScoreResults {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; 1 << 16],
+            result_sums: vec![0.0; 1 << 16],
+            count: 0,
+            result_sq_sum: 0.0,
+        }
+    }
+}
+
+impl ScoreResults {
+    fn gather<T: BulletFormat>(
+        loader: DataLoader<T>,
+        progress: &mut impl Progress,
+    ) -> io::Result<Self> {
+        let mut results = Self::default();
+        let total = loader.len();
+
+        loader.map_batches(16_384, |batch| {
+            batch.iter().for_each(|pos| results.push(pos));
+            progress.update(results.count, Some(total));
+        })?;
+
+        progress.finish();
+
+        Ok(results)
+    }
+
+    fn push<T: BulletFormat>(&mut self, pos: &T) {
+        let idx = usize::from(pos.score() as u16 ^ 0x8000);
+        let result = f64::from(pos.result());
+
+        self.counts[idx] += 1;
+        self.result_sums[idx] += result;
+        self.count += 1;
+        self.result_sq_sum += result * result;
+    }
+
+    /// Scores in increasing order with their number of positions and sum of
+    /// results.
+    fn iter(&self) -> impl Iterator<Item = (i32, usize, f64)> + '_ {
+        (0..1 << 16)
+            .filter(|&idx| self.counts[idx] > 0)
+            .map(|idx| (idx as i32 - 0x8000, self.counts[idx], self.result_sums[idx]))
+    }
+
+    fn correlation(&self) -> f64 {
+        let n = self.count as f64;
+        let (mut s, mut s2, mut r, mut sr) = (0.0, 0.0, 0.0, 0.0);
+
+        for (score, count, result_sum) in self.iter() {
+            let score = f64::from(score);
+            s += count as f64 * score;
+            s2 += count as f64 * score * score;
+            r += result_sum;
+            sr += score * result_sum;
+        }
+
+        let cov = n * sr - s * r;
+        let var = (n * s2 - s * s) * (n * self.result_sq_sum - r * r);
+
+        if var > 0.0 {
+            cov / var.sqrt()
+        } else {
+            0.0
+        }
+    }
+
+    /// The log-loss is convex in the scale, so this bisects on its derivative.
+    fn fit_scale(&self) -> f32 {
+        let derivative = |k: f64| {
+            self.iter()
+                .map(|(score, count, result_sum)| {
+                    let score = f64::from(score);
+                    let expected = 1.0 / (1.0 + (-k * score).exp());
+                    score * (count as f64 * expected - result_sum)
+                })
+                .sum::<f64>()
+        };
+
+        let (mut lo, mut hi) = (-1.0, 1.0);
+        for _ in 0..64 {
+            let mid = (lo + hi) / 2.0;
+            if derivative(mid) > 0.0 {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+
+        ((lo + hi) / 2.0) as f32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::{util::Rand, ChessBoard, NoProgress};
+
+    /// Positions with results drawn from `sigmoid(score, scale)`.
+    fn write_sampled(name: &str, scale: f32) -> PathBuf {
+        let mut rng = Rand::new(11);
+        let data = (0..100_000)
+            .map(|_| {
+                let score = rng.rand_int(2001) as i16 - 1000;
+                let win = rng.rand_f64() < f64::from(util::sigmoid(f32::from(score), scale));
+
+                ChessBoard {
+                    score,
+                    result: if win { 2 } else { 0 },
+                    ..Default::default()
+                }
+            })
+            .collect::<Vec<_>>();
+
+        util::write_test_file(name, None, &data)
+    }
+
+    #[test]
+    fn verdicts() {
+        let consistent = write_sampled("audit-consistent", 1. / 400.);
+        let inverted = write_sampled("audit-inverted", -1. / 400.);
+        let noise = write_sampled("audit-noise", 0.);
+
+        let audit = audit_file::<ChessBoard>(&consistent, 100, 0.1, &mut NoProgress).unwrap();
+        assert_eq!(audit.verdict, Verdict::Consistent);
+        assert!((audit.scale - 0.0025).abs() < 0.0001, "{}", audit.scale);
+        assert_eq!(audit.buckets.len(), 21);
+
+        let audit = audit_file::<ChessBoard>(&inverted, 100, 0.1, &mut NoProgress).unwrap();
+        assert_eq!(audit.verdict, Verdict::Inverted);
+        assert!(audit.scale < 0.0);
+
+        // results independent of the scores, which correlate slightly
+        // negatively by chance
+        let audit = audit_file::<ChessBoard>(&noise, 100, 0.1, &mut NoProgress).unwrap();
+        assert!(audit.correlation < 0.0);
+        assert_eq!(audit.verdict, Verdict::Weak);
+
+        for path in [consistent, inverted, noise] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn fitted_scale() {
+        let path = write_sampled("audit-scale", 1. / 400.);
+        let loader = DataLoader::<ChessBoard>::new(&path, 1).unwrap();
+
+        let scale = fit_scale(loader, &mut NoProgress).unwrap();
+        assert!((scale - 0.0025).abs() < 0.0001, "{scale}");
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -4,10 +4,10 @@ mod marlin;
 pub use cudad::{CudADFormat, CudADFormatIter};
 pub use marlin::{MarlinFormat, MarlinFormatIter};
 
-use crate::BulletFormat;
+use crate::{BulletFormat, Deduplicate, Pod};
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub struct ChessBoard {
     pub occ: u64,
     pub pcs: [u8; 16],
@@ -15,16 +15,21 @@ pub struct ChessBoard {
     pub result: u8,
     pub ksq: u8,
     pub opp_ksq: u8,
+    /// First byte holds the side-to-move.
     pub extra: [u8; 3],
 }
 
 const _RIGHT_SIZE: () = assert!(std::mem::size_of::<ChessBoard>() == 32);
 
+unsafe impl Pod for ChessBoard {}
+
 impl BulletFormat for ChessBoard {
     type FeatureType = (u8, u8);
 
     const HEADER_SIZE: usize = 0;
 
+    const FORMAT_ID: u16 = 1;
+
     fn score(&self) -> i16 {
         self.score
     }
@@ -40,6 +45,25 @@ impl BulletFormat for ChessBoard {
     fn set_result(&mut self, result: f32) {
         self.result = (2.0 * result) as u8;
     }
+
+    fn swap_bytes(&mut self) {
+        self.occ = self.occ.swap_bytes();
+        self.score = self.score.swap_bytes();
+    }
+}
+
+impl Deduplicate for ChessBoard {
+    fn without_outcome(&self) -> Self {
+        Self {
+            score: 0,
+            result: 0,
+            ..*self
+        }
+    }
+
+    fn set_score(&mut self, score: i16) {
+        self.score = score;
+    }
 }
 
 impl IntoIterator for ChessBoard {
@@ -92,6 +116,80 @@ impl ChessBoard {
         self.extra
     }
 
+    /// Side-to-move is 0 for White, 1 for Black.
+    pub fn stm(&self) -> usize {
+        usize::from(self.extra[0] & 1)
+    }
+
+    /// Material balance from the side-to-move's perspective, with pieces
+    /// valued at 1, 3, 3, 5 and 9 pawns.
+    pub fn material(&self) -> i32 {
+        const VALUES: [i32; 6] = [1, 3, 3, 5, 9, 0];
+
+        self.into_iter()
+            .map(|(piece, _)| {
+                let value = VALUES[usize::from(piece & 7)];
+                if piece & 8 > 0 {
+                    -value
+                } else {
+                    value
+                }
+            })
+            .sum()
+    }
+
+    /// Whether the side-to-move's king is attacked.
+    pub fn in_check(&self) -> bool {
+        let mut bbs = [0u64; 8];
+        for (piece, square) in *self {
+            bbs[usize::from(piece >> 3)] |= 1 << square;
+            bbs[2 + usize::from(piece & 7)] |= 1 << square;
+        }
+
+        let occ = bbs[0] | bbs[1];
+        let ksq = usize::from(self.ksq);
+        let theirs = |pc: usize| bbs[1] & bbs[2 + pc];
+
+        let (rank, file) = ((ksq / 8) as i32, (ksq % 8) as i32);
+        let attacked_by = |dirs: &[(i32, i32)], pieces: u64, slider: bool| {
+            dirs.iter().any(|&(dr, df)| {
+                let (mut r, mut f) = (rank + dr, file + df);
+                while (0..8).contains(&r) && (0..8).contains(&f) {
+                    let bit = 1 << (8 * r + f);
+                    if bit & occ > 0 || !slider {
+                        return bit & pieces > 0;
+                    }
+
+                    r += dr;
+                    f += df;
+                }
+
+                false
+            })
+        };
+
+        const PAWN: [(i32, i32); 2] = [(1, -1), (1, 1)];
+        const KNIGHT: [(i32, i32); 8] = [
+            (1, 2),
+            (2, 1),
+            (2, -1),
+            (1, -2),
+            (-1, -2),
+            (-2, -1),
+            (-2, 1),
+            (-1, 2),
+        ];
+        const DIAG: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+        const ORTH: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+        attacked_by(&PAWN, theirs(0), false)
+            || attacked_by(&KNIGHT, theirs(1), false)
+            || attacked_by(&DIAG, theirs(2) | theirs(4), true)
+            || attacked_by(&ORTH, theirs(3) | theirs(4), true)
+            || attacked_by(&DIAG, theirs(5), false)
+            || attacked_by(&ORTH, theirs(5), false)
+    }
+
     /// - Bitboards are in order White, Black, Pawn, Knight, Bishop, Rook, Queen, King.
     /// - Side-to-move is 0 for White, 1 for Black.
     /// - Score is White relative, in Centipawns.
@@ -148,7 +246,7 @@ impl ChessBoard {
             result,
             ksq,
             opp_ksq,
-            extra: [0; 3],
+            extra: [u8::from(stm == 1), 0, 0],
         })
     }
 }
@@ -169,7 +267,10 @@ impl std::str::FromStr for ChessBoard {
 
         let stm = u8::from(stm_str == "b");
 
-        let mut board = Self::default();
+        let mut board = Self {
+            extra: [stm, 0, 0],
+            ..Default::default()
+        };
 
         let mut idx = 0;
 
@@ -179,6 +280,10 @@ impl std::str::FromStr for ChessBoard {
                 if ('1'..='8').contains(&ch) {
                     col += ch.to_digit(10).expect("hard coded") as usize;
                 } else if let Some(mut piece) = "PNBRQKpnbrqk".chars().position(|el| el == ch) {
+                    if col > 7 {
+                        return Err("Malformed FEN!");
+                    }
+
                     let mut square = 8 * i + col;
 
                     piece = (piece / 6) << 3 | (piece % 6);
@@ -200,7 +305,7 @@ impl std::str::FromStr for ChessBoard {
                     board.occ |= 1 << square;
 
                     if idx >= 32 {
-                        return Err(s);
+                        return Err("Too Many Pieces!");
                     }
 
                     board.pcs[idx / 2] |= (piece as u8) << (4 * (idx & 1));
@@ -211,6 +316,10 @@ impl std::str::FromStr for ChessBoard {
             Ok(())
         };
 
+        if board_str.split('/').count() > 8 {
+            return Err(String::from("Malformed FEN!"));
+        }
+
         if stm == 1 {
             for (i, row) in board_str.split('/').enumerate() {
                 parse_row(7 - i, row)?;
@@ -224,7 +333,6 @@ impl std::str::FromStr for ChessBoard {
         board.score = if let Ok(x) = score.parse::<i16>() {
             x
         } else {
-            println!("{s}");
             return Err(String::from("Bad score!"));
         };
 
@@ -232,10 +340,7 @@ impl std::str::FromStr for ChessBoard {
             "1.0" | "[1.0]" | "1" => 2,
             "0.5" | "[0.5]" | "1/2" => 1,
             "0.0" | "[0.0]" | "0" => 0,
-            _ => {
-                println!("{s}");
-                return Err(String::from("Bad game result!"));
-            }
+            _ => return Err(String::from("Bad game result!")),
         };
 
         if stm == 1 {
@@ -246,3 +351,164 @@ impl std::str::FromStr for ChessBoard {
         Ok(board)
     }
 }
+
+/// Corrupt piece codes and results are written as `?`, so that suspect data
+/// can still be dumped.
+impl std::fmt::Display for ChessBoard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stm = self.stm();
+        let mut score = i32::from(self.score);
+        let mut result = (self.result <= 2).then_some(self.result);
+
+        if stm == 1 {
+            score = -score;
+            result = result.map(|result| 2 - result);
+        }
+
+        let mut mailbox = [None; 64];
+
+        for (mut piece, mut square) in *self {
+            if stm == 1 {
+                piece ^= 8;
+                square ^= 56;
+            }
+
+            mailbox[usize::from(square)] = Some(piece);
+        }
+
+        let mut fen = String::new();
+
+        for rank in (0..8).rev() {
+            let mut empty = 0;
+            for file in 0..8 {
+                if let Some(piece) = mailbox[8 * rank + file] {
+                    if empty > 0 {
+                        fen += empty.to_string().as_str();
+                        empty = 0;
+                    }
+
+                    fen.push(if piece & 7 < 6 {
+                        let idx = 6 * usize::from(piece >> 3) + usize::from(piece & 7);
+                        "PNBRQKpnbrqk".as_bytes()[idx].into()
+                    } else {
+                        '?'
+                    });
+                } else {
+                    empty += 1;
+                }
+            }
+
+            if empty > 0 {
+                fen += empty.to_string().as_str();
+            }
+
+            if rank > 0 {
+                fen += "/";
+            }
+        }
+
+        write!(f, "{fen} {} - - 0 1 | {score} | ", ["w", "b"][stm])?;
+
+        match result {
+            Some(result) => write!(f, "{:.1}", f32::from(result) / 2.0),
+            None => write!(f, "?"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ChessBoard;
+    use crate::BulletFormat;
+
+    #[test]
+    fn parse() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1 | 0 | 0.5",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b - - 0 1 | -35 | 0.0",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1 | 120 | 1.0",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R b - - 0 1 | 250 | 0.5",
+        ];
+
+        for fen in fens {
+            let board: ChessBoard = fen.parse().unwrap();
+            assert_eq!(board.to_string(), fen);
+        }
+    }
+
+    #[test]
+    fn from_raw() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b - - 0 1 | -35 | 0.0";
+        let parsed: ChessBoard = fen.parse().unwrap();
+
+        let mut bbs = [0; 8];
+        for (piece, square) in parsed {
+            let (piece, square) = (piece ^ 8, square ^ 56);
+            bbs[usize::from(piece >> 3)] |= 1 << square;
+            bbs[2 + usize::from(piece & 7)] |= 1 << square;
+        }
+
+        let board = ChessBoard::from_raw(bbs, 1, -35, 0.0).unwrap();
+        assert_eq!(board, parsed);
+        assert_eq!(board.to_string(), fen);
+    }
+
+    #[test]
+    fn in_check() {
+        let fens = [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1 | 0 | 0.5",
+                false,
+            ),
+            (
+                "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w - - 0 1 | 0 | 0.0",
+                true,
+            ),
+            (
+                "rnbqkbnr/ppppp2p/5p2/6pQ/4P3/8/PPPP1PPP/RNB1KBNR b - - 0 1 | 0 | 1.0",
+                true,
+            ),
+            ("4k3/3P4/8/8/8/8/8/4K3 b - - 0 1 | 0 | 0.5", true),
+            ("4k3/8/3P4/8/8/8/8/4K3 b - - 0 1 | 0 | 0.5", false),
+            ("4k3/8/8/8/8/8/5p2/4K3 w - - 0 1 | 0 | 0.5", true),
+            ("4k3/8/8/8/8/8/4p3/4K3 w - - 0 1 | 0 | 0.5", false),
+            ("4k3/8/5N2/8/8/8/8/4K3 b - - 0 1 | 0 | 0.5", true),
+            ("4k3/4p3/8/8/8/8/8/R3K3 b - - 0 1 | 0 | 0.5", false),
+            ("4k3/8/8/8/8/8/8/4K2r w - - 0 1 | 0 | 0.5", true),
+        ];
+
+        for (fen, check) in fens {
+            let board: ChessBoard = fen.parse().unwrap();
+            assert_eq!(board.in_check(), check, "{fen}");
+        }
+    }
+
+    #[test]
+    fn corrupt_display() {
+        let board = ChessBoard {
+            occ: 0b11,
+            pcs: [0x76, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            score: i16::MIN,
+            result: 3,
+            extra: [1, 0, 0],
+            ..Default::default()
+        };
+
+        assert_eq!(board.to_string(), "??6/8/8/8/8/8/8/8 b - - 0 1 | 32768 | ?");
+    }
+
+    #[test]
+    fn little_endian() {
+        let board = ChessBoard {
+            occ: 0x0102_0304_0506_0708,
+            score: -2,
+            ..Default::default()
+        };
+
+        let mut bytes = Vec::new();
+        ChessBoard::write_to_bin(&mut bytes, &[board]).unwrap();
+
+        assert_eq!(bytes[..8], [8, 7, 6, 5, 4, 3, 2, 1]);
+        assert_eq!(bytes[24..26], [0xFE, 0xFF]);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{BulletFormat, ChessBoard};
+use crate::{BulletFormat, ChessBoard, Pod};
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -11,8 +11,13 @@ pub struct CudADFormat {
     enp: u8,
     score: i16,
     wdl: i8,
+    _pad: u8,
 }
 
+const _RIGHT_SIZE: () = assert!(std::mem::size_of::<CudADFormat>() == 32);
+
+unsafe impl Pod for CudADFormat {}
+
 impl IntoIterator for CudADFormat {
     type Item = (u8, u8);
     type IntoIter = CudADFormatIter;
@@ -73,6 +78,8 @@ impl BulletFormat for CudADFormat {
 
     const HEADER_SIZE: usize = 1288;
 
+    const FORMAT_ID: u16 = 4;
+
     fn score(&self) -> i16 {
         if self.is_black_to_move() {
             -self.score
@@ -92,6 +99,11 @@ impl BulletFormat for CudADFormat {
     fn set_result(&mut self, result: f32) {
         self.wdl = (2.0 * result - 1.0) as i8;
     }
+
+    fn swap_bytes(&mut self) {
+        self.occ = self.occ.swap_bytes();
+        self.score = self.score.swap_bytes();
+    }
 }
 
 impl From<CudADFormat> for ChessBoard {
@@ -108,6 +120,8 @@ impl From<CudADFormat> for ChessBoard {
 
         board.result = cudad.res_stm();
 
+        board.extra[0] = u8::from(stm);
+
         let mut features = [(0, 0); 32];
         let mut fidx = 0;
 
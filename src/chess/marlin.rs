@@ -1,4 +1,4 @@
-use crate::{BulletFormat, ChessBoard};
+use crate::{BulletFormat, ChessBoard, Pod};
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -13,6 +13,10 @@ pub struct MarlinFormat {
     extra: u8,
 }
 
+const _RIGHT_SIZE: () = assert!(std::mem::size_of::<MarlinFormat>() == 32);
+
+unsafe impl Pod for MarlinFormat {}
+
 impl IntoIterator for MarlinFormat {
     type Item = (u8, u8);
     type IntoIter = MarlinFormatIter;
@@ -69,6 +73,8 @@ impl BulletFormat for MarlinFormat {
 
     const HEADER_SIZE: usize = 0;
 
+    const FORMAT_ID: u16 = 3;
+
     fn score(&self) -> i16 {
         if self.is_black_to_move() {
             -self.score
@@ -88,6 +94,12 @@ impl BulletFormat for MarlinFormat {
     fn set_result(&mut self, result: f32) {
         self.result = (2.0 * result) as u8;
     }
+
+    fn swap_bytes(&mut self) {
+        self.occ = self.occ.swap_bytes();
+        self.fmc = self.fmc.swap_bytes();
+        self.score = self.score.swap_bytes();
+    }
 }
 
 impl From<MarlinFormat> for ChessBoard {
@@ -104,6 +116,8 @@ impl From<MarlinFormat> for ChessBoard {
             board.result = mf.result;
         }
 
+        board.extra[0] = u8::from(stm == 1);
+
         let mut features = [(0, 0); 32];
         let mut fidx = 0;
 
@@ -1,57 +1,183 @@
 use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
     fs::File,
-    io::{self, BufRead, BufReader, BufWriter, Write},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
     path::Path,
     str::FromStr,
 };
 
-use crate::{BulletFormat, DataLoader};
+use crate::{loader::map_reader_batches, BulletFormat, DataLoader, FileHeader, Progress};
 
+/// Outcome of [`convert_from_text`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConversionReport {
+    pub lines_read: usize,
+    pub converted: usize,
+    pub failed: usize,
+    /// Number of failed lines for each parsing error.
+    pub failures: BTreeMap<String, usize>,
+}
+
+impl ConversionReport {
+    /// Fraction of lines that failed to parse.
+    pub fn error_rate(&self) -> f64 {
+        self.failed as f64 / self.lines_read.max(1) as f64
+    }
+}
+
+impl fmt::Display for ConversionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Lines Read : {}", self.lines_read)?;
+        writeln!(f, "Converted  : {}", self.converted)?;
+        writeln!(
+            f,
+            "Failed     : {} ({:.4}%)",
+            self.failed,
+            100.0 * self.error_rate()
+        )?;
+
+        for (error, count) in &self.failures {
+            writeln!(f, "  {error} : {count}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Lines are read in chunks, each of which is parsed across `threads`
+/// threads, with positions written in the same order as the input.
+///
+/// Lines that fail to parse are counted in the returned report and, if
+/// `rejects_path` is given, written there unchanged.
+///
+/// If `with_header` is set, the output starts with a [`FileHeader`].
 pub fn convert_from_text<U>(
     inp_path: impl AsRef<Path>,
     out_path: impl AsRef<Path>,
-) -> io::Result<()>
+    threads: usize,
+    rejects_path: Option<&Path>,
+    with_header: bool,
+    progress: &mut impl Progress,
+) -> io::Result<ConversionReport>
 where
     U: BulletFormat + FromStr<Err = String> + Send,
 {
-    let loader = BufReader::new(File::open(inp_path).unwrap());
+    let input = File::open(inp_path)?;
     let mut output = BufWriter::new(File::create(out_path)?);
-    let mut buffer = Vec::new();
-    let mut converted = 0;
-
-    for (i, line) in loader.lines().map(Result::unwrap).enumerate() {
-        match line.parse::<U>() {
-            Ok(position) => buffer.push(position),
-            Err(error) => {
-                println!("Error Parsing Line {}: {line}", i + 1);
-                println!("Error Type: {error}");
-            }
+    let mut rejects = rejects_path
+        .map(|path| File::create(path).map(BufWriter::new))
+        .transpose()?;
+
+    let report = convert_from_text_stream::<U>(
+        input,
+        &mut output,
+        threads,
+        rejects.as_mut().map(|rejects| rejects as &mut dyn Write),
+        with_header,
+        progress,
+    )?;
+
+    if with_header {
+        FileHeader::write_count(&mut output, report.converted)?;
+        output.flush()?;
+    }
+
+    Ok(report)
+}
+
+/// As [`convert_from_text`], but reading from and writing to arbitrary
+/// streams, e.g. stdin or an in-memory buffer. As the output cannot be
+/// rewound, any header records [`FileHeader::UNKNOWN_COUNT`].
+pub fn convert_from_text_stream<U>(
+    input: impl Read,
+    mut output: impl Write,
+    threads: usize,
+    mut rejects: Option<&mut dyn Write>,
+    with_header: bool,
+    progress: &mut impl Progress,
+) -> io::Result<ConversionReport>
+where
+    U: BulletFormat + FromStr<Err = String> + Send,
+{
+    const LINES_PER_THREAD: usize = 16_384;
+
+    if with_header {
+        FileHeader::unknown_count::<U>().write(&mut output)?;
+    }
+
+    let threads = threads.max(1);
+    let mut lines = BufReader::new(input).lines();
+    let mut chunk = Vec::with_capacity(threads * LINES_PER_THREAD);
+    let mut report = ConversionReport::default();
+
+    loop {
+        chunk.clear();
+        for line in lines.by_ref().take(threads * LINES_PER_THREAD) {
+            chunk.push(line?);
         }
 
-        if buffer.len() % 16_384 == 0 {
-            converted += buffer.len();
-            BulletFormat::write_to_bin(&mut output, &buffer).unwrap();
-            buffer.clear();
+        if chunk.is_empty() {
+            break;
+        }
+
+        let parsed = std::thread::scope(|s| {
+            chunk
+                .chunks(LINES_PER_THREAD)
+                .map(|lines| {
+                    s.spawn(move || {
+                        lines
+                            .iter()
+                            .map(|line| line.parse::<U>())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>()
+                .into_iter()
+                .flat_map(|p| p.join().unwrap())
+                .collect::<Vec<_>>()
+        });
 
-            if converted % (16_384 * 16) == 0 {
-                print!("> Converted {converted}\r");
+        let mut buffer = Vec::with_capacity(parsed.len());
+        for (line, result) in chunk.iter().zip(parsed) {
+            match result {
+                Ok(position) => buffer.push(position),
+                Err(error) => {
+                    report.failed += 1;
+                    *report.failures.entry(error).or_default() += 1;
+
+                    if let Some(rejects) = &mut rejects {
+                        writeln!(rejects, "{line}")?;
+                    }
+                }
             }
         }
+
+        report.lines_read += chunk.len();
+        report.converted += buffer.len();
+        BulletFormat::write_to_bin(&mut output, &buffer)?;
+
+        progress.update(report.lines_read, None);
     }
 
-    converted += buffer.len();
-    BulletFormat::write_to_bin(&mut output, &buffer).unwrap();
-    buffer.clear();
+    progress.finish();
 
-    println!("Total Positions: {converted}");
+    if let Some(rejects) = &mut rejects {
+        rejects.flush()?;
+    }
 
-    Ok(())
+    output.flush()?;
+
+    Ok(report)
 }
 
+/// If `with_header` is set, the output starts with a [`FileHeader`].
 pub fn convert_from_bin<T, U>(
     inp_path: impl AsRef<Path>,
     out_path: impl AsRef<Path>,
     threads: usize,
+    with_header: bool,
+    progress: &mut impl Progress,
 ) -> io::Result<()>
 where
     T: BulletFormat,
@@ -60,42 +186,306 @@ where
     let loader = DataLoader::<T>::new(inp_path, 512)?;
     let to_convert = loader.len();
     let mut output = BufWriter::new(File::create(out_path)?);
+
+    if with_header {
+        FileHeader::new::<U>(to_convert).write(&mut output)?;
+    }
     let batch_size = loader.max_batch_size();
     let mut converted_count = 0;
+    let mut result = Ok(());
 
     loader.map_batches(batch_size, |batch| {
+        if result.is_err() {
+            return;
+        }
+
         converted_count += batch.len();
-        let converted = std::thread::scope(|s| {
-            let chunk_size = batch.len() / threads + 1;
-            batch
-                .chunks(chunk_size)
-                .map(|chunk| {
-                    s.spawn(move || {
-                        let mut buffer = Vec::with_capacity(chunk.len());
-                        for &pos in chunk {
-                            buffer.push(U::from(pos));
-                        }
-                        buffer
-                    })
+        result = convert_batch::<T, U>(batch, threads, &mut output);
+        progress.update(converted_count, Some(to_convert));
+    })?;
+
+    progress.finish();
+
+    result?;
+    output.flush()
+}
+
+/// As [`convert_from_bin`], but reading from and writing to arbitrary
+/// streams. The input must start with the header of `T`, if it has one,
+/// optionally preceded by a [`FileHeader`]. As the output cannot be rewound,
+/// any header records [`FileHeader::UNKNOWN_COUNT`].
+pub fn convert_from_bin_stream<T, U>(
+    input: impl Read,
+    mut output: impl Write,
+    threads: usize,
+    with_header: bool,
+    progress: &mut impl Progress,
+) -> io::Result<()>
+where
+    T: BulletFormat,
+    U: BulletFormat + From<T>,
+{
+    let mut converted_count = 0;
+
+    if with_header {
+        FileHeader::unknown_count::<U>().write(&mut output)?;
+    }
+
+    map_reader_batches::<T>(input, 1 << 20, |batch| {
+        converted_count += batch.len();
+        convert_batch::<T, U>(batch, threads, &mut output)?;
+        progress.update(converted_count, None);
+        Ok(())
+    })?;
+
+    progress.finish();
+
+    output.flush()
+}
+
+fn convert_batch<T, U>(batch: &[T], threads: usize, output: &mut impl Write) -> io::Result<()>
+where
+    T: BulletFormat,
+    U: BulletFormat + From<T>,
+{
+    let converted = std::thread::scope(|s| {
+        let chunk_size = batch.len() / threads.max(1) + 1;
+        batch
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    let mut buffer = Vec::with_capacity(chunk.len());
+                    for &pos in chunk {
+                        buffer.push(U::from(pos));
+                    }
+                    buffer
                 })
-                .collect::<Vec<_>>()
-                .into_iter()
-                .map(|p| p.join().unwrap())
-                .collect::<Vec<_>>()
-        });
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|p| p.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    for part in converted {
+        BulletFormat::write_to_bin(output, &part)?;
+    }
+
+    Ok(())
+}
+
+pub fn convert_to_text<T>(
+    inp_path: impl AsRef<Path>,
+    out_path: impl AsRef<Path>,
+    progress: &mut impl Progress,
+) -> io::Result<()>
+where
+    T: BulletFormat + Display,
+{
+    let loader = DataLoader::<T>::new(inp_path, 512)?;
+    let to_convert = loader.len();
+    let mut output = BufWriter::new(File::create(out_path)?);
+    let batch_size = loader.max_batch_size();
+    let mut converted_count = 0;
+    let mut result = Ok(());
+
+    loader.map_batches(batch_size, |batch| {
+        if result.is_err() {
+            return;
+        }
+
+        result = batch.iter().try_for_each(|pos| writeln!(output, "{pos}"));
+        converted_count += batch.len();
+        progress.update(converted_count, Some(to_convert));
+    })?;
+
+    progress.finish();
+
+    result?;
+    output.flush()
+}
+
+/// As [`convert_to_text`], but reading from and writing to arbitrary
+/// streams. The input must start with the header of `T`, if it has one,
+/// optionally preceded by a [`FileHeader`].
+pub fn convert_to_text_stream<T>(
+    input: impl Read,
+    output: impl Write,
+    progress: &mut impl Progress,
+) -> io::Result<()>
+where
+    T: BulletFormat + Display,
+{
+    let mut output = BufWriter::new(output);
+    let mut converted_count = 0;
+
+    map_reader_batches::<T>(input, 16_384, |batch| {
+        batch.iter().try_for_each(|pos| writeln!(output, "{pos}"))?;
+        converted_count += batch.len();
+        progress.update(converted_count, None);
+        Ok(())
+    })?;
+
+    progress.finish();
 
-        for part in converted {
-            BulletFormat::write_to_bin(&mut output, &part).unwrap();
+    output.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fmt::Display, fs, path::PathBuf, str::FromStr};
+
+    use super::*;
+    use crate::{AtaxxBoard, ChessBoard, NoProgress};
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bulletformat-convert-{name}"))
+    }
+
+    fn round_trip<T>(name: &str, lines: &[&str], with_header: bool)
+    where
+        T: BulletFormat + FromStr<Err = String> + Display + Send,
+    {
+        let text = temp_file(&format!("{name}.txt"));
+        let bin = temp_file(&format!("{name}.bin"));
+        let out = temp_file(&format!("{name}.out.txt"));
+        fs::write(&text, lines.join("\n")).unwrap();
+
+        let report =
+            convert_from_text::<T>(&text, &bin, 2, None, with_header, &mut NoProgress).unwrap();
+        assert_eq!(report.converted, lines.len());
+
+        let header = FileHeader::read(&bin).unwrap();
+        assert_eq!(
+            header,
+            with_header.then(|| FileHeader::new::<T>(lines.len()))
+        );
+
+        convert_to_text::<T>(&bin, &out, &mut NoProgress).unwrap();
+        let output = fs::read_to_string(&out).unwrap();
+        assert_eq!(output.lines().collect::<Vec<_>>(), lines);
+
+        for path in [text, bin, out] {
+            fs::remove_file(path).unwrap();
         }
+    }
 
-        print!(
-            "> Converted {converted_count} / {to_convert} ({}%)\r",
-            100.0 * converted_count as f32 / to_convert as f32
+    #[test]
+    fn text_round_trip() {
+        round_trip::<ChessBoard>(
+            "chess",
+            &[
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1 | 0 | 0.5",
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b - - 0 1 | -35 | 0.0",
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1 | 120 | 1.0",
+            ],
+            true,
         );
-        let _ = std::io::stdout().flush();
-    });
 
-    println!();
+        round_trip::<AtaxxBoard>(
+            "ataxx",
+            &[
+                "6o/2x4/1xx4/1xo2oo/2oo3/7/5oo x 3 11 | -570 | 0.0",
+                "6o/7/5x1/3xxxx/4oxx/4ooo/3x1oo o 1 14 | 120 | 0.0",
+                "x1xx3/1x1x3/2-o-x1/x1ooxxx/2-o-xx/1oooooo/1oooxoo o 1 30 | -840 | 0.0",
+            ],
+            false,
+        );
+    }
 
-    Ok(())
+    #[test]
+    fn parallel_order() {
+        let scores = (0..100_000).map(|i| (i % 60_000 - 30_000) as i16);
+        let text = scores
+            .clone()
+            .map(|score| format!("8/8/8/8/8/8/8/K6k w - - 0 1 | {score} | 0.5\n"))
+            .collect::<String>();
+
+        let mut bin = Vec::new();
+        let report = convert_from_text_stream::<ChessBoard>(
+            text.as_bytes(),
+            &mut bin,
+            3,
+            None,
+            false,
+            &mut NoProgress,
+        )
+        .unwrap();
+        assert_eq!(report.converted, 100_000);
+
+        let mut converted = Vec::new();
+        map_reader_batches::<ChessBoard>(bin.as_slice(), 1000, |batch| {
+            converted.extend(batch.iter().map(|pos| pos.score));
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(converted.into_iter().eq(scores));
+    }
+
+    #[test]
+    fn report_and_rejects() {
+        let text = temp_file("malformed.txt");
+        let bin = temp_file("malformed.bin");
+        let rejects = temp_file("malformed.rejects.txt");
+
+        let good = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1 | 0 | 0.5";
+        let bad = [
+            "p7/8/8/8/8/8/8/8/8 w - - 0 1 | 0 | 0.5",
+            "p7/8/8/8/8/8/8/8/8 b - - 0 1 | 0 | 0.5",
+            "8p/8/8/8/8/8/8/8 w - - 0 1 | 0 | 0.5",
+            "8/8/8/8/8/8/8/8 w - - 0 1 | x | 0.5",
+            "8/8/8/8/8/8/8/8 w - - 0 1",
+        ];
+
+        let lines = [good, bad[0], bad[1], good, bad[2], bad[3], bad[4]];
+        fs::write(&text, lines.join("\n")).unwrap();
+
+        let report =
+            convert_from_text::<ChessBoard>(&text, &bin, 2, Some(&rejects), false, &mut NoProgress)
+                .unwrap();
+
+        assert_eq!(report.lines_read, 7);
+        assert_eq!(report.converted, 2);
+        assert_eq!(report.failed, 5);
+        assert_eq!(report.failures["Malformed FEN!"], 3);
+        assert_eq!(report.failures["Bad score!"], 1);
+        assert_eq!(report.failures["Malformed!"], 1);
+
+        let rejected = fs::read_to_string(&rejects).unwrap();
+        assert_eq!(rejected.lines().collect::<Vec<_>>(), bad);
+        assert_eq!(fs::metadata(&bin).unwrap().len(), 2 * 32);
+
+        for path in [text, bin, rejects] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn stream_header() {
+        let bin = temp_file("stream.bin");
+        let text = "8/8/8/8/8/8/8/K6k w - - 0 1 | 10 | 0.5\n".repeat(3);
+
+        let mut output = File::create(&bin).unwrap();
+        convert_from_text_stream::<ChessBoard>(
+            text.as_bytes(),
+            &mut output,
+            2,
+            None,
+            true,
+            &mut NoProgress,
+        )
+        .unwrap();
+
+        let header = FileHeader::read(&bin).unwrap().unwrap();
+        assert_eq!(header.count, FileHeader::UNKNOWN_COUNT);
+
+        let loader = DataLoader::<ChessBoard>::new(&bin, 1).unwrap();
+        assert_eq!(loader.len(), 3);
+
+        FileHeader::write_count(&mut output, 3).unwrap();
+        assert_eq!(FileHeader::read(&bin).unwrap().unwrap().count, 3);
+
+        fs::remove_file(bin).unwrap();
+    }
 }
@@ -0,0 +1,268 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::{self, File},
+    hash::{BuildHasher, BuildHasherDefault, DefaultHasher, Hash},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    header,
+    util::{self, Reporter},
+    BulletFormat, DataLoader, Progress,
+};
+
+/// Formats whose positions can be compared independently of their outcome.
+pub trait Deduplicate: BulletFormat + Eq + Hash {
+    /// Copy of the position with everything but the board cleared, such as
+    /// the score, result and move counters.
+    fn without_outcome(&self) -> Self;
+
+    fn set_score(&mut self, score: i16);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Keep the first occurrence of each position as is.
+    KeepFirst,
+    /// Keep the first occurrence of each position, with its score and
+    /// result replaced by the mean over all occurrences.
+    Average,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DedupSummary {
+    pub read: usize,
+    pub written: usize,
+}
+
+impl DedupSummary {
+    pub fn removed(&self) -> usize {
+        self.read - self.written
+    }
+}
+
+/// Partitions at this depth are deduplicated in memory whatever their size,
+/// as they may consist of copies of a few positions that cannot be split.
+const MAX_DEPTH: usize = 4;
+
+/// Removes duplicate positions from a file of any size.
+///
+/// Positions are compared by board only, unless `include_outcome` is set,
+/// in which case the score and result must also match. Positions are first
+/// partitioned by hash into temporary files alongside `out_path`, and any
+/// partition still too large for `memory_mb` is partitioned again in turn,
+/// so the output is grouped by partition rather than in the input order.
+///
+/// Each position is counted twice in the progress, once when it is first
+/// partitioned and once when its partition is deduplicated.
+pub fn dedup_file<T: Deduplicate>(
+    inp_path: impl AsRef<Path>,
+    out_path: impl AsRef<Path>,
+    include_outcome: bool,
+    policy: DuplicatePolicy,
+    memory_mb: usize,
+    progress: &mut impl Progress,
+) -> io::Result<DedupSummary> {
+    let (budget, load_mb) = util::memory_budget::<T>(memory_mb);
+
+    let loader = DataLoader::<T>::new(&inp_path, load_mb)?;
+    let read = loader.len();
+
+    let headers = util::read_header::<T>(&inp_path)?;
+    let mut output = BufWriter::new(File::create(&out_path)?);
+    output.write_all(&headers)?;
+
+    let mut deduper = Deduper {
+        include_outcome,
+        policy,
+        budget,
+        load_mb,
+        reporter: Reporter::new(progress, 2 * read),
+    };
+
+    let written = deduper.dedup(loader, out_path.as_ref(), &mut output, 0)?;
+    deduper.reporter.finish();
+
+    header::update_count(&mut output, &headers, written)?;
+    output.flush()?;
+
+    Ok(DedupSummary { read, written })
+}
+
+struct Deduper<'a, P> {
+    include_outcome: bool,
+    policy: DuplicatePolicy,
+    budget: usize,
+    load_mb: usize,
+    reporter: Reporter<'a, P>,
+}
+
+fn key<T: Deduplicate>(pos: &T, include_outcome: bool) -> T {
+    if include_outcome {
+        *pos
+    } else {
+        pos.without_outcome()
+    }
+}
+
+impl<P: Progress> Deduper<'_, P> {
+    /// Writes the unique positions from `loader` to `output`, partitioning
+    /// them into files named after `temp_base` if they do not fit in memory,
+    /// and returns the number written.
+    fn dedup<T: Deduplicate>(
+        &mut self,
+        loader: DataLoader<T>,
+        temp_base: &Path,
+        output: &mut impl Write,
+        depth: usize,
+    ) -> io::Result<usize> {
+        // rough cost of storing a position along with its hash table entry
+        let entry_size = 3 * std::mem::size_of::<T>() + 32;
+        let len = loader.len();
+
+        if len * entry_size <= self.budget || depth == MAX_DEPTH {
+            let unique = self.dedup_in_memory(loader, depth == 0)?;
+            BulletFormat::write_to_bin(output, &unique)?;
+            self.reporter.report(len);
+            return Ok(unique.len());
+        }
+
+        let num_parts = (len * entry_size)
+            .div_ceil(self.budget)
+            .clamp(2, util::MAX_TEMP_FILES);
+
+        let hasher = BuildHasherDefault::<DefaultHasher>::default();
+        let include_outcome = self.include_outcome;
+
+        // mixing in the depth splits up positions that shared a partition
+        // at the previous depth
+        let parts = util::scatter(
+            loader,
+            temp_base,
+            num_parts,
+            self.budget,
+            (depth == 0).then_some(&mut self.reporter),
+            |pos| hasher.hash_one((depth, key(pos, include_outcome))) as usize % num_parts,
+        )?;
+
+        let mut written = 0;
+
+        for path in parts.paths() {
+            let part = DataLoader::<T>::headerless(path, self.load_mb)?;
+            written += self.dedup(part, path, output, depth + 1)?;
+            fs::remove_file(path)?;
+        }
+
+        Ok(written)
+    }
+
+    fn dedup_in_memory<T: Deduplicate>(
+        &mut self,
+        loader: DataLoader<T>,
+        top: bool,
+    ) -> io::Result<Vec<T>> {
+        struct Occurrences {
+            idx: usize,
+            score: i64,
+            result: usize,
+            count: usize,
+        }
+
+        let mut unique = Vec::new();
+        let mut seen = HashMap::<T, Occurrences>::new();
+        let batch_size = loader.max_batch_size();
+
+        loader.map_batches(batch_size, |batch| {
+            if top {
+                self.reporter.report(batch.len());
+            }
+
+            for pos in batch {
+                match seen.entry(key(pos, self.include_outcome)) {
+                    Entry::Occupied(mut entry) => {
+                        let occ = entry.get_mut();
+                        occ.score += i64::from(pos.score());
+                        occ.result += pos.result_idx();
+                        occ.count += 1;
+                    }
+                    Entry::Vacant(entry) => {
+                        entry.insert(Occurrences {
+                            idx: unique.len(),
+                            score: i64::from(pos.score()),
+                            result: pos.result_idx(),
+                            count: 1,
+                        });
+                        unique.push(*pos);
+                    }
+                }
+            }
+        })?;
+
+        if self.policy == DuplicatePolicy::Average {
+            for occ in seen.values().filter(|occ| occ.count > 1) {
+                let count = occ.count as f64;
+                let pos = &mut unique[occ.idx];
+                pos.set_score((occ.score as f64 / count).round() as i16);
+                pos.set_result(((occ.result as f64 / count).round() / 2.0) as f32);
+            }
+        }
+
+        Ok(unique)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::{ChessBoard, NoProgress};
+
+    #[test]
+    fn recursive_partitions() {
+        // three copies of each position, with scores averaging to the index
+        let data = [0, 1, -1]
+            .into_iter()
+            .flat_map(|offset| {
+                let mut copy = util::test_positions(0..10_000);
+                copy.iter_mut().for_each(|pos| pos.score += offset);
+                copy
+            })
+            .collect::<Vec<_>>();
+
+        let inp = util::write_test_file("dedup-inp", None, &data);
+        let out = util::test_path("dedup-out");
+
+        // the smallest budget, so partitions have to be partitioned again
+        let summary = dedup_file::<ChessBoard>(
+            &inp,
+            &out,
+            false,
+            DuplicatePolicy::Average,
+            0,
+            &mut NoProgress,
+        )
+        .unwrap();
+        assert_eq!(
+            summary,
+            DedupSummary {
+                read: 30_000,
+                written: 10_000
+            }
+        );
+
+        let mut unique = Vec::new();
+        DataLoader::<ChessBoard>::new(&out, 1)
+            .unwrap()
+            .map_positions(|pos| unique.push(*pos))
+            .unwrap();
+
+        assert!(unique.iter().all(|pos| pos.occ == pos.score as u64));
+        unique.sort_unstable_by_key(|pos| pos.occ);
+        assert!(unique.iter().map(|pos| pos.occ).eq(0..10_000));
+
+        fs::remove_file(inp).unwrap();
+        fs::remove_file(out).unwrap();
+    }
+}
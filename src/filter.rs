@@ -0,0 +1,87 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{header, util, BulletFormat, ChessBoard, DataLoader, Progress};
+
+/// Writes the positions in `inp_path` for which `keep` returns true to
+/// `out_path`, returning the number of positions kept.
+pub fn filter_file<T, F>(
+    inp_path: impl AsRef<Path>,
+    out_path: impl AsRef<Path>,
+    mut keep: F,
+    progress: &mut impl Progress,
+) -> io::Result<usize>
+where
+    T: BulletFormat,
+    F: FnMut(&T) -> bool,
+{
+    let loader = DataLoader::<T>::new(&inp_path, 512)?;
+    let to_filter = loader.len();
+    let headers = util::read_header::<T>(&inp_path)?;
+    let mut output = BufWriter::new(File::create(&out_path)?);
+    output.write_all(&headers)?;
+
+    let batch_size = loader.max_batch_size();
+    let mut buffer = Vec::<T>::with_capacity(batch_size);
+    let mut filtered = 0;
+    let mut kept = 0;
+    let mut result = Ok(());
+
+    loader.map_batches(batch_size, |batch| {
+        if result.is_err() {
+            return;
+        }
+
+        buffer.extend(batch.iter().filter(|pos| keep(pos)).copied());
+        kept += buffer.len();
+        filtered += batch.len();
+        result = BulletFormat::write_to_bin(&mut output, &buffer);
+        buffer.clear();
+
+        progress.update(filtered, Some(to_filter));
+    })?;
+
+    progress.finish();
+
+    result?;
+    header::update_count(&mut output, &headers, kept)?;
+    output.flush()?;
+
+    Ok(kept)
+}
+
+/// Keeps positions with absolute score at most `max`.
+pub fn score_within<T: BulletFormat>(max: i16) -> impl Fn(&T) -> bool {
+    move |pos| pos.score().unsigned_abs() <= max.unsigned_abs()
+}
+
+/// Keeps positions unless the score is beyond `margin` in favour of the
+/// side that went on to lose.
+pub fn score_agrees_with_result<T: BulletFormat>(margin: i16) -> impl Fn(&T) -> bool {
+    move |pos| {
+        let score = pos.score();
+        match pos.result_idx() {
+            0 => score <= margin,
+            2 => score >= -margin,
+            _ => true,
+        }
+    }
+}
+
+/// Keeps positions with between `min` and `max` pieces, kings included.
+pub fn piece_count(min: u32, max: u32) -> impl Fn(&ChessBoard) -> bool {
+    move |pos| (min..=max).contains(&pos.occ().count_ones())
+}
+
+/// Keeps positions whose absolute material balance is at most `max` pawns.
+pub fn material_within(max: i32) -> impl Fn(&ChessBoard) -> bool {
+    move |pos| pos.material().abs() <= max
+}
+
+/// Keeps positions where the side-to-move is not in check.
+pub fn not_in_check() -> impl Fn(&ChessBoard) -> bool {
+    |pos| !pos.in_check()
+}
@@ -0,0 +1,169 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::BulletFormat;
+
+/// Optional header identifying the contents of a file, which precedes any
+/// header of the format itself. Files without one are still readable, but
+/// cannot be checked against the format they are loaded as.
+///
+/// It is stored as 32 little-endian bytes: the magic, version, format id,
+/// record size, count and flags, followed by 4 reserved zero bytes. As it
+/// has a fixed size, a writer that does not know the count in advance can
+/// write a placeholder and overwrite it with [`FileHeader::write_count`]
+/// once finished, or if it cannot seek, record [`FileHeader::UNKNOWN_COUNT`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileHeader {
+    pub version: u16,
+    /// [`BulletFormat::FORMAT_ID`] of the positions in the file.
+    pub format_id: u16,
+    /// Size in bytes of each position.
+    pub record_size: u32,
+    /// Number of positions in the file.
+    pub count: u64,
+    /// Reserved for future use, currently always 0.
+    pub flags: u32,
+}
+
+impl FileHeader {
+    pub const MAGIC: [u8; 8] = *b"BULLETFM";
+    pub const VERSION: u16 = 1;
+    pub const SIZE: usize = 32;
+
+    /// Count recorded by writers that cannot go back to fill it in, which
+    /// is accepted for any number of positions.
+    pub const UNKNOWN_COUNT: u64 = u64::MAX;
+
+    const COUNT_OFFSET: u64 = 16;
+
+    pub fn new<T: BulletFormat>(count: usize) -> Self {
+        Self {
+            version: Self::VERSION,
+            format_id: T::FORMAT_ID,
+            record_size: std::mem::size_of::<T>() as u32,
+            count: count as u64,
+            flags: 0,
+        }
+    }
+
+    /// Header for a file whose count is not known when it is written.
+    pub fn unknown_count<T: BulletFormat>() -> Self {
+        Self {
+            count: Self::UNKNOWN_COUNT,
+            ..Self::new::<T>(0)
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..8].copy_from_slice(&Self::MAGIC);
+        bytes[8..10].copy_from_slice(&self.version.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.format_id.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.record_size.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.count.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.flags.to_le_bytes());
+        bytes
+    }
+
+    /// Parses a header from the start of `bytes`, or `None` if they do not
+    /// start with the magic.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < Self::SIZE || bytes[..8] != Self::MAGIC {
+            return None;
+        }
+
+        Some(Self {
+            version: u16::from_le_bytes(bytes[8..10].try_into().unwrap()),
+            format_id: u16::from_le_bytes(bytes[10..12].try_into().unwrap()),
+            record_size: u32::from_le_bytes(bytes[12..16].try_into().unwrap()),
+            count: u64::from_le_bytes(bytes[16..24].try_into().unwrap()),
+            flags: u32::from_le_bytes(bytes[24..28].try_into().unwrap()),
+        })
+    }
+
+    pub fn write(&self, output: &mut impl Write) -> io::Result<()> {
+        output.write_all(&self.to_bytes())
+    }
+
+    /// Overwrites the count in the header at the start of `output`, then
+    /// returns to the current position.
+    pub fn write_count(output: &mut (impl Write + Seek), count: usize) -> io::Result<()> {
+        let pos = output.stream_position()?;
+        output.seek(SeekFrom::Start(Self::COUNT_OFFSET))?;
+        output.write_all(&(count as u64).to_le_bytes())?;
+        output.seek(SeekFrom::Start(pos))?;
+        Ok(())
+    }
+
+    /// Reads the header of the file at `path`, or `None` for a legacy file
+    /// without one.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        let mut prefix = Vec::with_capacity(Self::SIZE);
+        File::open(path)?
+            .take(Self::SIZE as u64)
+            .read_to_end(&mut prefix)?;
+
+        Ok(Self::from_bytes(&prefix))
+    }
+
+    /// Checks that the file holds positions of type `T`.
+    pub fn validate<T: BulletFormat>(&self) -> io::Result<()> {
+        let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        let record_size = std::mem::size_of::<T>();
+
+        if self.version > Self::VERSION {
+            return invalid(format!(
+                "file header version {} is newer than the supported version {}",
+                self.version,
+                Self::VERSION,
+            ));
+        }
+
+        if self.format_id != T::FORMAT_ID {
+            return invalid(format!(
+                "file holds positions of format id {}, expected format id {}",
+                self.format_id,
+                T::FORMAT_ID,
+            ));
+        }
+
+        if self.record_size as usize != record_size {
+            return invalid(format!(
+                "file holds {} byte positions, expected {record_size} byte positions",
+                self.record_size,
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Copy of the headers read from a file, with `count` recorded in the file
+/// header if there is one.
+pub(crate) fn with_count(headers: &[u8], count: usize) -> Vec<u8> {
+    let mut headers = headers.to_vec();
+
+    if let Some(mut header) = FileHeader::from_bytes(&headers) {
+        header.count = count as u64;
+        headers[..FileHeader::SIZE].copy_from_slice(&header.to_bytes());
+    }
+
+    headers
+}
+
+/// Records `count` in the file header at the start of `output`, if
+/// `headers` (as written) has one.
+pub(crate) fn update_count(
+    output: &mut (impl Write + Seek),
+    headers: &[u8],
+    count: usize,
+) -> io::Result<()> {
+    if FileHeader::from_bytes(headers).is_some() {
+        FileHeader::write_count(output, count)?;
+    }
+
+    Ok(())
+}
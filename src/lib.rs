@@ -1,25 +1,52 @@
 mod ataxx;
+mod audit;
 pub mod chess;
 mod convert;
+mod dedup;
+mod filter;
+mod header;
 mod loader;
+mod progress;
+mod shuffle;
+mod split;
+mod stats;
 mod util;
 
 use std::{
-    fs::File,
-    io::{self, BufWriter, Write},
+    io::{self, Write},
     marker::Sized,
 };
 
 pub use ataxx::AtaxxBoard;
+pub use audit::{audit_file, fit_scale, Audit, ScoreBucket, Verdict};
 pub use chess::ChessBoard;
-pub use convert::{convert_from_bin, convert_from_text};
-pub use loader::DataLoader;
+pub use convert::{
+    convert_from_bin, convert_from_bin_stream, convert_from_text, convert_from_text_stream,
+    convert_to_text, convert_to_text_stream, ConversionReport,
+};
+pub use dedup::{dedup_file, DedupSummary, Deduplicate, DuplicatePolicy};
+pub use filter::{
+    filter_file, material_within, not_in_check, piece_count, score_agrees_with_result, score_within,
+};
+pub use header::FileHeader;
+pub use loader::{Batches, Checkpoint, DataLoader, EpochLoader, MmapDataLoader, MultiDataLoader};
+pub use progress::{NoProgress, Progress, TerminalProgress};
+pub use shuffle::shuffle_file;
+pub use split::{split_file, SplitMethod, SplitSize, SplitSummary};
+pub use stats::{chess_dataset_stats, dataset_stats, ChessStats, Stats};
+pub use util::Pod;
 
-pub trait BulletFormat: IntoIterator + Sized + Copy + Send + Sync {
+/// Positions are stored on disk as their `#[repr(C)]` layout, with every
+/// multi-byte field little-endian, optionally preceded by a header of
+/// `HEADER_SIZE` bytes, and before that by an optional [`FileHeader`].
+pub trait BulletFormat: Pod + IntoIterator + Sized + Copy + Send + Sync {
     type FeatureType;
 
     const HEADER_SIZE: usize;
 
+    /// Identifies the format in a [`FileHeader`], so must be unique.
+    const FORMAT_ID: u16;
+
     fn set_result(&mut self, result: f32);
 
     fn score(&self) -> i16;
@@ -28,17 +55,43 @@ pub trait BulletFormat: IntoIterator + Sized + Copy + Send + Sync {
 
     fn result_idx(&self) -> usize;
 
+    /// A suitable `scale` for a dataset can be found with [`fit_scale`].
     fn blended_result(&self, blend: f32, scale: f32) -> f32 {
         blend * self.result() + (1. - blend) * util::sigmoid(f32::from(self.score()), scale)
     }
 
+    /// Reverses the byte order of every multi-byte field.
+    fn swap_bytes(&mut self);
+
+    /// Converts positions in the on-disk byte order to host byte order.
+    fn from_le(data: &mut [Self]) {
+        if cfg!(target_endian = "big") {
+            data.iter_mut().for_each(Self::swap_bytes);
+        }
+    }
+
+    /// Converts positions in host byte order to the on-disk byte order.
+    fn to_le(data: &mut [Self]) {
+        Self::from_le(data);
+    }
+
+    /// Bytes of the positions in host byte order, which only matches the
+    /// on-disk format on little-endian hosts, see [`BulletFormat::write_to_bin`].
     fn as_bytes_slice(data: &[Self]) -> &[u8] {
-        util::to_slice_with_lifetime(data)
+        util::bytes_of(data)
     }
 
-    fn write_to_bin(output: &mut BufWriter<File>, data: &[Self]) -> io::Result<()> {
-        let data_slice = util::to_slice_with_lifetime(data);
-        output.write_all(data_slice)?;
+    fn write_to_bin(output: &mut impl Write, data: &[Self]) -> io::Result<()> {
+        if cfg!(target_endian = "little") {
+            return output.write_all(util::bytes_of(data));
+        }
+
+        for chunk in data.chunks(1024) {
+            let mut encoded = chunk.to_vec();
+            Self::to_le(&mut encoded);
+            output.write_all(util::bytes_of(&encoded))?;
+        }
+
         Ok(())
     }
 }
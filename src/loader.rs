@@ -1,15 +1,33 @@
+mod batches;
+mod epoch;
+mod mmap;
+mod multi;
+
+pub use batches::Batches;
+pub use epoch::{Checkpoint, EpochLoader};
+pub use mmap::MmapDataLoader;
+pub use multi::MultiDataLoader;
+
 use std::{
     fs::File,
-    io::{self, Read},
+    io::{self, Read, Seek, SeekFrom},
     marker::PhantomData,
+    ops::Range,
     path::Path,
 };
 
-use crate::{util, BulletFormat};
+use crate::{
+    util::{self, Rand},
+    BulletFormat, FileHeader,
+};
 
 pub struct DataLoader<T> {
     file: File,
+    layout: Layout,
     buffer_size: usize,
+    shuffle: Option<Rand>,
+    skip_partial: bool,
+    shard: Shard,
     marker: PhantomData<T>,
 }
 
@@ -17,93 +35,887 @@ impl<T: BulletFormat> DataLoader<T> {
     const DATA_SIZE: usize = std::mem::size_of::<T>();
 
     pub fn new(path: impl AsRef<Path>, buffer_size_mb: usize) -> io::Result<Self> {
-        Ok(Self {
-            file: File::open(path)?,
+        let file = File::open(path)?;
+        let layout = Layout::read::<T>(&file)?;
+        Ok(Self::with_layout(file, layout, buffer_size_mb))
+    }
+
+    /// Opens a temporary file of positions written without any headers.
+    pub(crate) fn headerless(path: impl AsRef<Path>, buffer_size_mb: usize) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let layout = Layout::headerless::<T>(file.metadata()?.len())?;
+        Ok(Self::with_layout(file, layout, buffer_size_mb))
+    }
+
+    fn with_layout(file: File, layout: Layout, buffer_size_mb: usize) -> Self {
+        Self {
+            file,
+            layout,
             buffer_size: buffer_size_mb * 1024 * 1024,
+            shuffle: None,
+            skip_partial: false,
+            shard: Shard::default(),
             marker: PhantomData,
-        })
+        }
+    }
+
+    /// Restricts the loader to the `index`th of `count` equal contiguous
+    /// parts of the file, so that separate workers see disjoint positions.
+    pub fn shard(mut self, index: usize, count: usize) -> Self {
+        self.shard = Shard::new(index, count);
+        self
+    }
+
+    /// By default a file that ends partway through a position is rejected
+    /// when read; this instead skips the trailing bytes, see
+    /// [`DataLoader::skipped_bytes`].
+    pub fn skip_trailing_partial(mut self) -> Self {
+        self.skip_partial = true;
+        self
+    }
+
+    /// Shuffles the positions in each load of `buffer_size_mb` before it
+    /// is split into batches, so the buffer size acts as the shuffle window.
+    /// The order is reproducible for a given `seed`.
+    pub fn with_shuffling(mut self, seed: u64) -> Self {
+        self.shuffle = Some(Rand::new(seed));
+        self
+    }
+
+    fn shuffle_load(rng: &mut Option<Rand>, buffer: &mut [T]) {
+        if let Some(rng) = rng {
+            rng.shuffle(buffer);
+        }
+    }
+
+    /// Number of trailing bytes skipped after the last whole position, which
+    /// is 0 unless [`DataLoader::skip_trailing_partial`] is set.
+    pub fn skipped_bytes(&self) -> u64 {
+        self.layout.skipped_bytes(self.skip_partial)
+    }
+
+    /// The file header, if the file has one.
+    pub fn file_header(&self) -> Option<FileHeader> {
+        self.layout.header
     }
 
     pub fn len(&self) -> usize {
-        (self.file.metadata().unwrap().len() as usize - T::HEADER_SIZE) / Self::DATA_SIZE
+        self.shard.range(self.layout.len::<T>()).len()
     }
 
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
 
-    pub fn map_batches<F: FnMut(&[T])>(mut self, batch_size: usize, mut f: F) {
-        let batches_per_load = self.buffer_size / Self::DATA_SIZE / batch_size;
-        let cap = Self::DATA_SIZE * batch_size * batches_per_load;
+    /// Reads the position at `index`, counting from the start of the shard.
+    pub fn get(&self, index: usize) -> io::Result<T> {
+        let end = index.checked_add(1).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("index {index} out of bounds for {} positions", self.len()),
+            )
+        })?;
 
-        if T::HEADER_SIZE > 0 {
-            let mut header = vec![0; T::HEADER_SIZE];
-            self.file.read_exact(&mut header).unwrap();
+        Ok(self.read_range(index..end)?[0])
+    }
+
+    /// Reads the positions in `range`, counting from the start of the shard.
+    pub fn read_range(&self, range: Range<usize>) -> io::Result<Vec<T>> {
+        let data = data_range::<T>(self.layout, self.skip_partial, self.shard)?;
+        let len = self.len();
+
+        if range.start > range.end || range.end > len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("range {range:?} out of bounds for {len} positions"),
+            ));
         }
 
-        let mut buffer = vec![0; cap];
-        loop {
-            let bytes_read = self.file.read(&mut buffer).unwrap();
+        let offset = data.start + (range.start * Self::DATA_SIZE) as u64;
+        let mut buffer = util::zeroed_vec(range.len());
 
-            if bytes_read == 0 {
-                break;
-            }
+        // positional reads leave the cursor alone, so that this can be
+        // called from several threads at once
+        util::read_exact_at(&self.file, util::bytes_of_mut(&mut buffer), offset)
+            .map_err(|err| read_error(offset, err))?;
 
-            let data = util::to_slice_with_lifetime(&buffer[..bytes_read]);
+        T::from_le(&mut buffer);
+        Ok(buffer)
+    }
 
-            for batch in data.chunks(batch_size) {
-                f(batch);
-            }
+    pub fn map_batches<F: FnMut(&[T])>(self, batch_size: usize, mut f: F) -> io::Result<()> {
+        let mut batches = self.batches(batch_size)?;
+
+        while let Some(batch) = batches.next_batch()? {
+            f(batch);
         }
+
+        Ok(())
+    }
+
+    /// Reads through the file once, yielding batches on demand.
+    pub fn batches(self, batch_size: usize) -> io::Result<Batches<T>> {
+        let cap = self.load_size(batch_size);
+        let reader = RecordReader::new::<T>(self.file, self.layout, self.skip_partial, self.shard)?;
+        Ok(Batches::new(reader, cap, batch_size, self.shuffle))
+    }
+
+    /// Number of positions to read at a time, a whole number of batches.
+    fn load_size(&self, batch_size: usize) -> usize {
+        let batches_per_load = (self.buffer_size / Self::DATA_SIZE / batch_size).max(1);
+        batch_size * batches_per_load
+    }
+
+    /// Cycles through the file indefinitely, rather than once.
+    pub fn into_epochs(self, batch_size: usize) -> io::Result<EpochLoader<T>> {
+        let cap = self.load_size(batch_size);
+        EpochLoader::new(
+            self.file,
+            self.layout,
+            self.skip_partial,
+            self.shard,
+            cap,
+            batch_size,
+            self.shuffle,
+        )
     }
 
     pub fn max_batch_size(&self) -> usize {
         self.buffer_size / Self::DATA_SIZE
     }
 
-    pub fn map_positions<F: FnMut(&T)>(self, mut f: F) {
+    pub fn map_positions<F: FnMut(&T)>(self, mut f: F) -> io::Result<()> {
         let batch_size = self.max_batch_size();
         self.map_batches(batch_size, |batch| {
             for pos in batch {
                 f(pos);
             }
-        });
+        })
     }
 
-    pub fn map_batches_threaded_loading<F: FnMut(&[T])>(mut self, batch_size: usize, mut f: F) {
+    pub fn map_batches_threaded_loading<F: FnMut(&[T])>(
+        self,
+        batch_size: usize,
+        mut f: F,
+    ) -> io::Result<()> {
         use std::sync::mpsc::sync_channel;
 
-        let batches_per_load = self.buffer_size / Self::DATA_SIZE / batch_size;
-        let cap = Self::DATA_SIZE * batch_size * batches_per_load;
+        let cap = self.load_size(batch_size);
+        let mut reader =
+            RecordReader::new::<T>(self.file, self.layout, self.skip_partial, self.shard)?;
+        let mut shuffle = self.shuffle;
 
-        let (sender, reciever) = sync_channel::<Vec<u8>>(2);
+        let (sender, reciever) = sync_channel::<Vec<T>>(2);
 
-        let dataloader = std::thread::spawn(move || {
-            if T::HEADER_SIZE > 0 {
-                let mut header = vec![0; T::HEADER_SIZE];
-                self.file.read_exact(&mut header).unwrap();
-            }
-
-            let mut buffer = vec![0; cap];
+        let dataloader = std::thread::spawn(move || -> io::Result<()> {
+            let mut buffer = util::zeroed_vec(cap);
             loop {
-                let bytes_read = self.file.read(&mut buffer).unwrap();
+                let read = reader.read(&mut buffer)?;
 
-                if bytes_read == 0 {
+                if read == 0 {
                     break;
                 }
 
-                sender.send(buffer.to_vec()).unwrap();
+                Self::shuffle_load(&mut shuffle, &mut buffer[..read]);
+
+                // only fails if the receiver has hung up
+                if sender.send(buffer[..read].to_vec()).is_err() {
+                    break;
+                }
             }
-        });
 
-        while let Ok(buf) = reciever.recv() {
-            let data = util::to_slice_with_lifetime(&buf);
+            Ok(())
+        });
 
+        while let Ok(data) = reciever.recv() {
             for batch in data.chunks(batch_size) {
                 f(batch);
             }
         }
 
-        dataloader.join().unwrap();
+        dataloader
+            .join()
+            .map_err(|_| io::Error::other("data loading thread panicked"))?
+    }
+}
+
+/// Reads whole positions from a file, after validating its size and
+/// skipping its header.
+struct RecordReader {
+    file: File,
+    offset: u64,
+    remaining: u64,
+}
+
+impl RecordReader {
+    fn new<T: BulletFormat>(
+        mut file: File,
+        layout: Layout,
+        skip_partial: bool,
+        shard: Shard,
+    ) -> io::Result<Self> {
+        let range = data_range::<T>(layout, skip_partial, shard)?;
+
+        file.seek(SeekFrom::Start(range.start))
+            .map_err(|err| read_error(range.start, err))?;
+
+        Ok(Self {
+            file,
+            offset: range.start,
+            remaining: range.end - range.start,
+        })
+    }
+
+    /// Fills as much of `buffer` as there is data left for, returning the
+    /// number of positions read, or 0 once finished.
+    fn read<T: BulletFormat>(&mut self, buffer: &mut [T]) -> io::Result<usize> {
+        let bytes = util::bytes_of_mut(buffer);
+        let to_read = bytes.len().min(self.remaining as usize);
+        let bytes_read = util::read_full(&mut self.file, &mut bytes[..to_read])
+            .map_err(|err| read_error(self.offset, err))?;
+
+        self.offset += bytes_read as u64;
+        self.remaining -= bytes_read as u64;
+
+        if bytes_read < to_read {
+            let err = io::Error::new(io::ErrorKind::UnexpectedEof, "file was truncated");
+            return Err(read_error(self.offset, err));
+        }
+
+        let read = bytes_read / std::mem::size_of::<T>();
+        T::from_le(&mut buffer[..read]);
+        Ok(read)
+    }
+}
+
+fn read_error(offset: u64, err: io::Error) -> io::Error {
+    io::Error::new(
+        err.kind(),
+        format!("failed to read data at byte offset {offset}: {err}"),
+    )
+}
+
+/// Streams batches of positions from any reader, which must start with the
+/// header of `T`, if it has one, optionally preceded by a file header.
+pub(crate) fn map_reader_batches<T: BulletFormat>(
+    mut input: impl Read,
+    batch_size: usize,
+    mut f: impl FnMut(&[T]) -> io::Result<()>,
+) -> io::Result<()> {
+    let data_size = std::mem::size_of::<T>();
+
+    let mut prefix = [0; FileHeader::SIZE];
+    let prefix_len = util::read_full(&mut input, &mut prefix).map_err(|err| read_error(0, err))?;
+    let file_header = FileHeader::from_bytes(&prefix[..prefix_len]);
+
+    // without a file header, the bytes read so far belong to what follows
+    let mut offset = 0;
+    let mut input = match file_header {
+        Some(header) => {
+            header.validate::<T>()?;
+            offset = FileHeader::SIZE as u64;
+            (&[] as &[u8]).chain(input)
+        }
+        None => (&prefix[..prefix_len]).chain(input),
+    };
+
+    let mut header = vec![0; T::HEADER_SIZE];
+    input
+        .read_exact(&mut header)
+        .map_err(|err| read_error(offset, err))?;
+
+    offset += T::HEADER_SIZE as u64;
+    let mut buffer = util::zeroed_vec::<T>(batch_size);
+
+    loop {
+        let bytes_read = util::read_full(&mut input, util::bytes_of_mut(&mut buffer))
+            .map_err(|err| read_error(offset, err))?;
+
+        if bytes_read == 0 {
+            return Ok(());
+        }
+
+        let whole = bytes_read / data_size * data_size;
+        if whole < bytes_read {
+            let err = io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("stream ended partway through a {data_size} byte position"),
+            );
+            return Err(read_error(offset + whole as u64, err));
+        }
+
+        offset += bytes_read as u64;
+        let batch = &mut buffer[..bytes_read / data_size];
+        T::from_le(batch);
+        f(batch)?;
+    }
+}
+
+/// Contiguous part of the positions in a file, see [`DataLoader::shard`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Shard {
+    index: usize,
+    count: usize,
+}
+
+impl Default for Shard {
+    fn default() -> Self {
+        Self { index: 0, count: 1 }
+    }
+}
+
+impl Shard {
+    fn new(index: usize, count: usize) -> Self {
+        assert!(
+            index < count,
+            "Shard index {index} out of range for {count} shards!"
+        );
+        Self { index, count }
+    }
+
+    fn range(&self, len: usize) -> Range<usize> {
+        let start = len * self.index / self.count;
+        let end = len * (self.index + 1) / self.count;
+        start..end
+    }
+}
+
+/// Where the positions are in a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Layout {
+    /// Bytes before the first position, including the file header.
+    header_size: u64,
+    /// Bytes of whole positions.
+    data_size: u64,
+    /// Bytes after the last whole position.
+    trailing: u64,
+    header: Option<FileHeader>,
+}
+
+impl Layout {
+    /// Detects and validates the file header, if there is one.
+    pub(crate) fn read<T: BulletFormat>(file: &File) -> io::Result<Self> {
+        let file_size = file.metadata()?.len();
+        let mut prefix = Vec::with_capacity(FileHeader::SIZE);
+
+        let mut file = file;
+        file.seek(SeekFrom::Start(0))
+            .and_then(|_| file.take(FileHeader::SIZE as u64).read_to_end(&mut prefix))
+            .map_err(|err| read_error(0, err))?;
+
+        Self::from_prefix::<T>(&prefix, file_size)
+    }
+
+    fn from_prefix<T: BulletFormat>(prefix: &[u8], file_size: u64) -> io::Result<Self> {
+        let header = FileHeader::from_bytes(prefix);
+        let mut header_size = T::HEADER_SIZE as u64;
+
+        if let Some(header) = header {
+            header.validate::<T>()?;
+            header_size += FileHeader::SIZE as u64;
+        }
+
+        Self::new::<T>(file_size, header_size, header)
+    }
+
+    fn headerless<T: BulletFormat>(file_size: u64) -> io::Result<Self> {
+        Self::new::<T>(file_size, 0, None)
+    }
+
+    /// Checks the file consists of its headers followed by as many positions
+    /// as the file header records, and possibly a trailing partial position.
+    fn new<T: BulletFormat>(
+        file_size: u64,
+        header_size: u64,
+        header: Option<FileHeader>,
+    ) -> io::Result<Self> {
+        let record_size = std::mem::size_of::<T>() as u64;
+
+        if file_size < header_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("file size {file_size} is smaller than the {header_size} byte header"),
+            ));
+        }
+
+        let trailing = (file_size - header_size) % record_size;
+        let data_size = file_size - header_size - trailing;
+
+        let wrong_count = |header: &FileHeader| {
+            header.count != FileHeader::UNKNOWN_COUNT && header.count != data_size / record_size
+        };
+
+        if let Some(header) = header.filter(wrong_count) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "file header records {} positions, but the file holds {}",
+                    header.count,
+                    data_size / record_size,
+                ),
+            ));
+        }
+
+        Ok(Self {
+            header_size,
+            data_size,
+            trailing,
+            header,
+        })
+    }
+
+    pub(crate) fn header_size(&self) -> usize {
+        self.header_size as usize
+    }
+
+    fn len<T>(&self) -> usize {
+        self.data_size as usize / std::mem::size_of::<T>()
+    }
+
+    /// Bytes skipped at the end of the file, see [`DataLoader::skipped_bytes`].
+    fn skipped_bytes(&self, skip_partial: bool) -> u64 {
+        if skip_partial {
+            self.trailing
+        } else {
+            0
+        }
+    }
+}
+
+/// Byte range in the file of the positions in the given shard, rejecting a
+/// trailing partial position unless it is to be skipped.
+fn data_range<T: BulletFormat>(
+    layout: Layout,
+    skip_partial: bool,
+    shard: Shard,
+) -> io::Result<Range<u64>> {
+    let Layout {
+        header_size,
+        data_size,
+        trailing,
+        ..
+    } = layout;
+
+    if trailing != 0 && !skip_partial {
+        let record_size = std::mem::size_of::<T>();
+        let file_size = header_size + data_size + trailing;
+
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "file size {file_size} minus the {header_size} byte header is not a multiple of \
+                 the {record_size} byte position size, found {trailing} trailing bytes at byte \
+                 offset {}",
+                header_size + data_size,
+            ),
+        ));
+    }
+
+    let Range { start, end } = shard.range(layout.len::<T>());
+    let to_offset = |idx: usize| header_size + (idx * std::mem::size_of::<T>()) as u64;
+    Ok(to_offset(start)..to_offset(end))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::{self, File},
+        io::Write,
+        path::PathBuf,
+    };
+
+    use super::{Checkpoint, DataLoader, MmapDataLoader, MultiDataLoader};
+    use crate::{util, AtaxxBoard, ChessBoard, FileHeader};
+
+    fn write_positions(name: &str, count: usize, trailing: &[u8]) -> PathBuf {
+        write_file(name, None, count, trailing)
+    }
+
+    fn write_file(
+        name: &str,
+        header: Option<FileHeader>,
+        count: usize,
+        trailing: &[u8],
+    ) -> PathBuf {
+        let positions = util::test_positions(0..count);
+        let path = util::write_test_file(&format!("loader-{name}"), header, &positions);
+
+        File::options()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(trailing)
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn trailing_partial() {
+        let path = write_positions("partial", 1000, &[1, 2, 3]);
+
+        let loader = DataLoader::<ChessBoard>::new(&path, 1).unwrap();
+        assert_eq!(loader.len(), 1000);
+        assert_eq!(loader.skipped_bytes(), 0);
+        assert!(loader.map_batches(7, |_| {}).is_err());
+
+        let loader = DataLoader::<ChessBoard>::new(&path, 1)
+            .unwrap()
+            .skip_trailing_partial();
+        assert_eq!(loader.skipped_bytes(), 3);
+
+        let mut scores = Vec::new();
+        loader
+            .map_batches_threaded_loading(7, |batch| {
+                scores.extend(batch.iter().map(|pos| pos.score))
+            })
+            .unwrap();
+
+        assert_eq!(scores, (0..1000).collect::<Vec<_>>());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn resume_epochs() {
+        let path = write_positions("epochs", 50_000, &[]);
+        let epochs = || {
+            DataLoader::<ChessBoard>::new(&path, 1)
+                .unwrap()
+                .with_shuffling(42)
+                .into_epochs(1000)
+                .unwrap()
+        };
+
+        let mut loader = epochs();
+        let mut checkpoint = None;
+        let mut expected = Vec::new();
+
+        for i in 0..200 {
+            if i == 120 {
+                checkpoint = Some(loader.checkpoint());
+            }
+
+            let batch = loader.next_batch().unwrap();
+            if i >= 120 {
+                expected.push(batch.to_vec());
+            }
+        }
+
+        assert_eq!(loader.epoch(), 3);
+
+        let mut resumed = epochs().resume(checkpoint.unwrap()).unwrap();
+        assert_eq!(resumed.epoch(), 2);
+
+        for batch in expected {
+            assert_eq!(resumed.next_batch().unwrap(), batch);
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn epoch_retry() {
+        let path = write_positions("epoch-retry", 100_000, &[]);
+        let mut loader = DataLoader::<ChessBoard>::new(&path, 1)
+            .unwrap()
+            .into_epochs(1000)
+            .unwrap();
+
+        // the whole of the first load
+        for _ in 0..32 {
+            loader.next_batch().unwrap();
+        }
+
+        let offset = loader.offset();
+        let checkpoint = loader.checkpoint();
+
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(40_000 * 32)
+            .unwrap();
+
+        for _ in 0..3 {
+            let err = loader.next_batch().unwrap_err();
+            assert!(err.to_string().contains("byte offset 1280000"), "{err}");
+            assert_eq!(loader.offset(), offset);
+            assert_eq!(loader.checkpoint(), checkpoint);
+        }
+
+        write_positions("epoch-retry", 100_000, &[]);
+        assert_eq!(loader.next_batch().unwrap()[0].occ, 32_000);
+        assert_eq!(loader.epoch(), 0);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn shuffling() {
+        let path = write_positions("shuffling", 30_000, &[]);
+        let loader = |seed| {
+            DataLoader::<ChessBoard>::new(&path, 1)
+                .unwrap()
+                .with_shuffling(seed)
+        };
+
+        let sequential = |seed| {
+            let mut scores = Vec::new();
+            loader(seed)
+                .map_batches(1000, |batch| {
+                    scores.extend(batch.iter().map(|pos| pos.score))
+                })
+                .unwrap();
+            scores
+        };
+
+        let mut threaded = Vec::new();
+        loader(5)
+            .map_batches_threaded_loading(1000, |batch| {
+                threaded.extend(batch.iter().map(|pos| pos.score))
+            })
+            .unwrap();
+
+        let scores = sequential(5);
+        assert_eq!(scores, sequential(5));
+        assert_eq!(scores, threaded);
+        assert_ne!(scores, sequential(6));
+        assert_ne!(scores, (0..30_000).collect::<Vec<_>>());
+
+        threaded.sort_unstable();
+        assert_eq!(threaded, (0..30_000).collect::<Vec<_>>());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn shards() {
+        let path = write_positions("shards", 1001, &[]);
+        let mut scores = Vec::new();
+
+        for index in 0..3 {
+            let loader = DataLoader::<ChessBoard>::new(&path, 1)
+                .unwrap()
+                .shard(index, 3);
+            assert_eq!(loader.len(), [333, 334, 334][index]);
+            loader.map_positions(|pos| scores.push(pos.score)).unwrap();
+        }
+
+        assert_eq!(scores, (0..1001).collect::<Vec<_>>());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn random_access() {
+        let path = write_positions("random-access", 1000, &[]);
+        let loader = DataLoader::<ChessBoard>::new(&path, 1).unwrap().shard(1, 2);
+
+        assert_eq!(loader.get(0).unwrap().score, 500);
+        assert_eq!(loader.get(499).unwrap().score, 999);
+        assert!(loader.get(500).is_err());
+        assert!(loader.get(usize::MAX).is_err());
+
+        let range = loader.read_range(10..20).unwrap();
+        assert_eq!(
+            range.iter().map(|pos| pos.score).collect::<Vec<_>>(),
+            (510..520).collect::<Vec<_>>()
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn file_header() {
+        let header = FileHeader::new::<ChessBoard>(100);
+        let path = write_file("header", Some(header), 100, &[]);
+
+        let loader = DataLoader::<ChessBoard>::new(&path, 1).unwrap();
+        assert_eq!(loader.file_header(), Some(header));
+        assert_eq!(loader.len(), 100);
+        assert_eq!(loader.get(10).unwrap().score, 10);
+
+        let mut scores = Vec::new();
+        loader.map_positions(|pos| scores.push(pos.score)).unwrap();
+        assert_eq!(scores, (0..100).collect::<Vec<_>>());
+
+        assert!(DataLoader::<AtaxxBoard>::new(&path, 1).is_err());
+
+        let mismatched = write_file("header-count", Some(header), 99, &[]);
+        assert!(DataLoader::<ChessBoard>::new(&mismatched, 1).is_err());
+
+        for path in [path, mismatched] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn mmap_matches_reader() {
+        let header = FileHeader::new::<ChessBoard>(1000);
+        let with_header = write_file("mmap-header", Some(header), 1000, &[]);
+        let partial = write_positions("mmap-partial", 1000, &[1, 2, 3]);
+
+        let open = |path, shard: Option<usize>, skip| {
+            let mut reader = DataLoader::<ChessBoard>::new(path, 1).unwrap();
+            let mut mmap = MmapDataLoader::<ChessBoard>::new(path).unwrap();
+
+            if let Some(index) = shard {
+                reader = reader.shard(index, 3);
+                mmap = mmap.shard(index, 3);
+            }
+
+            if skip {
+                reader = reader.skip_trailing_partial();
+                mmap = mmap.skip_trailing_partial();
+            }
+
+            assert_eq!(mmap.len(), reader.len());
+            assert_eq!(mmap.skipped_bytes(), reader.skipped_bytes());
+            assert_eq!(mmap.file_header(), reader.file_header());
+
+            let mut expected = Vec::new();
+            let read = reader.map_positions(|pos| expected.push(*pos));
+            let mapped = mmap.positions().map(|positions| positions.to_vec());
+            assert_eq!(read.is_ok(), mapped.is_ok());
+
+            mapped.ok().inspect(|mapped| assert_eq!(*mapped, expected))
+        };
+
+        let scores =
+            |positions: Vec<ChessBoard>| positions.iter().map(|pos| pos.score).collect::<Vec<_>>();
+
+        let all = open(&with_header, None, false).unwrap();
+        assert_eq!(scores(all), (0..1000).collect::<Vec<_>>());
+
+        let shard = open(&with_header, Some(1), false).unwrap();
+        assert_eq!(scores(shard), (333..666).collect::<Vec<_>>());
+
+        assert!(open(&partial, None, false).is_none());
+        assert_eq!(open(&partial, None, true).unwrap().len(), 1000);
+
+        for path in [with_header, partial] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn weighted_interleaving() {
+        let heavy = write_positions("multi-heavy", 20_000, &[]);
+
+        // negative scores, to tell the sources apart
+        let mut data = util::test_positions(0..20_000);
+        data.iter_mut().for_each(|pos| pos.score = -1 - pos.score);
+        let light = util::write_test_file("loader-multi-light", None, &data);
+
+        // the unused source is left out of the count
+        let sources = [(&heavy, 3.0), (&light, 1.0), (&heavy, 0.0)];
+        let loader = MultiDataLoader::<ChessBoard>::new(&sources, 1)
+            .unwrap()
+            .with_seed(3);
+        assert_eq!(loader.len(), 40_000);
+
+        let mut scores = Vec::new();
+        loader.map_positions(|pos| scores.push(pos.score)).unwrap();
+
+        // until the heavy source runs out, it supplies about 3 in 4 positions
+        let heavy_share = scores[..20_000].iter().filter(|&&s| s >= 0).count();
+        assert!((14_500..15_500).contains(&heavy_share), "{heavy_share}");
+
+        // after which the light source continues alone
+        assert!(scores[28_000..].iter().all(|&s| s < 0));
+
+        // every position appears once, in order within its source
+        let from_heavy = scores.iter().filter(|&&s| s >= 0).copied();
+        let from_light = scores.iter().filter(|&&s| s < 0).map(|s| -1 - s);
+        assert!(from_heavy.eq(0..20_000));
+        assert!(from_light.eq(0..20_000));
+
+        for weight in [-1.0, f64::NAN, f64::INFINITY] {
+            let sources = [(&heavy, 1.0), (&light, weight)];
+            let err = MultiDataLoader::<ChessBoard>::new(&sources, 1)
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+
+        for path in [heavy, light] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn invalid_checkpoint() {
+        let path = write_file(
+            "checkpoint",
+            Some(FileHeader::new::<ChessBoard>(100)),
+            100,
+            &[],
+        );
+        let epochs = || {
+            DataLoader::<ChessBoard>::new(&path, 1)
+                .unwrap()
+                .into_epochs(10)
+                .unwrap()
+        };
+
+        // offsets before the first position, within a position, or past the end
+        for offset in [0, 33, 32 + 3200] {
+            let checkpoint = Checkpoint {
+                offset,
+                ..Default::default()
+            };
+            assert!(epochs().resume(checkpoint).is_err());
+        }
+
+        let checkpoint = Checkpoint {
+            offset: 32 + 320,
+            ..Default::default()
+        };
+        let mut resumed = epochs().resume(checkpoint).unwrap();
+        assert_eq!(resumed.next_batch().unwrap()[0].score, 10);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn batches_stop_after_error() {
+        let path = write_positions("truncated", 1000, &[]);
+        let batches = DataLoader::<ChessBoard>::new(&path, 1)
+            .unwrap()
+            .batches(100)
+            .unwrap();
+
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(500 * 32)
+            .unwrap();
+
+        let results = batches.collect::<Vec<_>>();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn concurrent_random_access() {
+        let path = write_positions("concurrent", 20_000, &[]);
+        let loader = DataLoader::<ChessBoard>::new(&path, 1).unwrap();
+
+        std::thread::scope(|s| {
+            for thread in 0..8 {
+                let loader = &loader;
+                s.spawn(move || {
+                    for i in 0..20_000 {
+                        let idx = (i + thread * 2_500) * 7919 % 20_000;
+                        assert_eq!(loader.get(idx).unwrap().score, idx as i16);
+                    }
+                });
+            }
+        });
+
+        fs::remove_file(path).unwrap();
     }
 }
@@ -0,0 +1,81 @@
+use std::io;
+
+use super::RecordReader;
+use crate::{
+    util::{self, Rand},
+    BulletFormat,
+};
+
+/// Pull-based alternative to [`DataLoader::map_batches`](super::DataLoader::map_batches).
+///
+/// Batches can be borrowed from the internal buffer with [`Batches::next_batch`],
+/// or taken as owned `Vec`s through the [`Iterator`] implementation, which
+/// allows stopping early and combining with other iterators.
+///
+/// Created by [`DataLoader::batches`](super::DataLoader::batches).
+pub struct Batches<T> {
+    reader: RecordReader,
+    buffer: Vec<T>,
+    filled: usize,
+    pos: usize,
+    batch_size: usize,
+    shuffle: Option<Rand>,
+    done: bool,
+}
+
+impl<T: BulletFormat> Batches<T> {
+    pub(super) fn new(
+        reader: RecordReader,
+        cap: usize,
+        batch_size: usize,
+        shuffle: Option<Rand>,
+    ) -> Self {
+        Self {
+            reader,
+            buffer: util::zeroed_vec(cap),
+            filled: 0,
+            pos: 0,
+            batch_size,
+            shuffle,
+            done: false,
+        }
+    }
+
+    /// The next batch, or `None` once the file is exhausted or after an
+    /// error has been returned.
+    pub fn next_batch(&mut self) -> io::Result<Option<&[T]>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        if self.pos == self.filled {
+            let read = self.reader.read(&mut self.buffer);
+            self.done = !matches!(read, Ok(filled) if filled > 0);
+            self.filled = read?;
+            self.pos = 0;
+
+            if self.done {
+                return Ok(None);
+            }
+
+            if let Some(rng) = &mut self.shuffle {
+                rng.shuffle(&mut self.buffer[..self.filled]);
+            }
+        }
+
+        let start = self.pos;
+        self.pos = (start + self.batch_size).min(self.filled);
+
+        Ok(Some(&self.buffer[start..self.pos]))
+    }
+}
+
+impl<T: BulletFormat> Iterator for Batches<T> {
+    type Item = io::Result<Vec<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_batch()
+            .map(|batch| batch.map(<[T]>::to_vec))
+            .transpose()
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    fs::File,
+    io::{self, Seek, SeekFrom},
+    ops::Range,
+};
+
+use super::{data_range, read_error, Layout, Shard};
+use crate::{
+    util::{self, Rand},
+    BulletFormat,
+};
+
+/// Position in an [`EpochLoader`] that it can later be resumed from, given
+/// the same file, buffer size, batch size and shard.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub epoch: usize,
+    /// Byte offset in the file of the current load.
+    pub offset: u64,
+    /// Index of the next batch within the current load.
+    pub batch: usize,
+    /// Shuffling state before the current load was shuffled.
+    pub shuffle: Option<u64>,
+}
+
+/// Cycles through a file batch by batch, indefinitely, keeping track of the
+/// epoch and position so that it can be checkpointed and resumed.
+///
+/// Created by [`DataLoader::into_epochs`](super::DataLoader::into_epochs).
+pub struct EpochLoader<T> {
+    file: File,
+    data_start: u64,
+    data_end: u64,
+    buffer: Vec<T>,
+    filled: usize,
+    batch_size: usize,
+    epoch: usize,
+    load_offset: u64,
+    batch: usize,
+    shuffle: Option<Rand>,
+    load_shuffle: Option<u64>,
+}
+
+impl<T: BulletFormat> EpochLoader<T> {
+    const DATA_SIZE: usize = std::mem::size_of::<T>();
+
+    pub(super) fn new(
+        file: File,
+        layout: Layout,
+        skip_partial: bool,
+        shard: Shard,
+        cap: usize,
+        batch_size: usize,
+        shuffle: Option<Rand>,
+    ) -> io::Result<Self> {
+        let Range {
+            start: data_start,
+            end: data_end,
+        } = data_range::<T>(layout, skip_partial, shard)?;
+
+        if data_start == data_end {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "cannot cycle through a file with no positions",
+            ));
+        }
+
+        let mut loader = Self {
+            file,
+            data_start,
+            data_end,
+            buffer: util::zeroed_vec(cap),
+            filled: 0,
+            batch_size,
+            epoch: 0,
+            load_offset: data_start,
+            batch: 0,
+            shuffle,
+            load_shuffle: None,
+        };
+
+        loader.load(0, data_start)?;
+
+        Ok(loader)
+    }
+
+    /// Continues from a checkpoint taken from a loader over the same file,
+    /// with the same buffer size, batch size and shard.
+    pub fn resume(mut self, checkpoint: Checkpoint) -> io::Result<Self> {
+        let Checkpoint {
+            epoch,
+            offset,
+            batch,
+            shuffle,
+        } = checkpoint;
+
+        let in_range = (self.data_start..self.data_end).contains(&offset);
+        if !in_range || !(offset - self.data_start).is_multiple_of(Self::DATA_SIZE as u64) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("checkpoint offset {offset} is not the start of a position in the file"),
+            ));
+        }
+
+        self.shuffle = shuffle.map(Rand::new);
+        self.load(epoch, offset)?;
+        self.batch = batch;
+
+        Ok(self)
+    }
+
+    pub fn epoch(&self) -> usize {
+        self.epoch
+    }
+
+    /// Byte offset in the file up to which positions have been handed out.
+    pub fn offset(&self) -> u64 {
+        let consumed = (self.batch * self.batch_size).min(self.filled);
+        self.load_offset + (consumed * Self::DATA_SIZE) as u64
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            epoch: self.epoch,
+            offset: self.load_offset,
+            batch: self.batch,
+            shuffle: self.load_shuffle,
+        }
+    }
+
+    /// The next batch, moving on to the next epoch at the end of the file.
+    /// The last batch of each load may be smaller than the batch size.
+    ///
+    /// After an error, the loader is left as it was, so the same load is
+    /// attempted again by the next call.
+    pub fn next_batch(&mut self) -> io::Result<&[T]> {
+        if self.batch * self.batch_size >= self.filled {
+            let mut epoch = self.epoch;
+            let mut offset = self.load_offset + (self.filled * Self::DATA_SIZE) as u64;
+
+            if offset == self.data_end {
+                epoch += 1;
+                offset = self.data_start;
+            }
+
+            self.load(epoch, offset)?;
+        }
+
+        let start = self.batch * self.batch_size;
+        let end = (start + self.batch_size).min(self.filled);
+        self.batch += 1;
+
+        Ok(&self.buffer[start..end])
+    }
+
+    /// Fills the buffer from `offset`, only moving there once it succeeds.
+    fn load(&mut self, epoch: usize, offset: u64) -> io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .map_err(|err| read_error(offset, err))?;
+
+        let buffer = util::bytes_of_mut(&mut self.buffer);
+        let to_read = buffer.len().min((self.data_end - offset) as usize);
+        let bytes_read = util::read_full(&mut self.file, &mut buffer[..to_read])
+            .map_err(|err| read_error(offset, err))?;
+
+        if bytes_read < to_read {
+            let err = io::Error::new(io::ErrorKind::UnexpectedEof, "file was truncated");
+            return Err(read_error(offset + bytes_read as u64, err));
+        }
+
+        self.epoch = epoch;
+        self.load_offset = offset;
+        self.filled = bytes_read / Self::DATA_SIZE;
+        self.batch = 0;
+        T::from_le(&mut self.buffer[..self.filled]);
+        self.load_shuffle = self.shuffle.map(|rng| rng.state());
+
+        if let Some(rng) = &mut self.shuffle {
+            rng.shuffle(&mut self.buffer[..self.filled]);
+        }
+
+        Ok(())
+    }
+}
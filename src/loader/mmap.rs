@@ -0,0 +1,97 @@
+use std::{borrow::Cow, fs::File, io, marker::PhantomData, path::Path};
+
+use memmap2::Mmap;
+
+use super::{data_range, Layout, Shard};
+use crate::{util, BulletFormat, FileHeader};
+
+/// Zero-copy alternative to [`DataLoader`](super::DataLoader) that maps the
+/// file into memory, so batches are handed out directly from the page cache
+/// and shared between processes reading the same file.
+///
+/// The file must not be modified while it is mapped.
+pub struct MmapDataLoader<T> {
+    mmap: Mmap,
+    layout: Layout,
+    skip_partial: bool,
+    shard: Shard,
+    marker: PhantomData<T>,
+}
+
+impl<T: BulletFormat> MmapDataLoader<T> {
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        let layout = Layout::read::<T>(&file)?;
+
+        Ok(Self {
+            mmap,
+            layout,
+            skip_partial: false,
+            shard: Shard::default(),
+            marker: PhantomData,
+        })
+    }
+
+    /// See [`DataLoader::shard`](super::DataLoader::shard).
+    pub fn shard(mut self, index: usize, count: usize) -> Self {
+        self.shard = Shard::new(index, count);
+        self
+    }
+
+    /// See [`DataLoader::skip_trailing_partial`](super::DataLoader::skip_trailing_partial).
+    pub fn skip_trailing_partial(mut self) -> Self {
+        self.skip_partial = true;
+        self
+    }
+
+    /// See [`DataLoader::skipped_bytes`](super::DataLoader::skipped_bytes).
+    pub fn skipped_bytes(&self) -> u64 {
+        self.layout.skipped_bytes(self.skip_partial)
+    }
+
+    /// See [`DataLoader::file_header`](super::DataLoader::file_header).
+    pub fn file_header(&self) -> Option<FileHeader> {
+        self.layout.header
+    }
+
+    pub fn len(&self) -> usize {
+        self.shard.range(self.layout.len::<T>()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All positions in the file (or shard), after validating its size.
+    ///
+    /// These are borrowed from the mapping, unless the header leaves them
+    /// misaligned or the host is big-endian, in which case they are copied.
+    pub fn positions(&self) -> io::Result<Cow<'_, [T]>> {
+        let range = data_range::<T>(self.layout, self.skip_partial, self.shard)?;
+        let bytes = &self.mmap[range.start as usize..range.end as usize];
+        let mut positions = util::cast_slice_or_copy(bytes);
+
+        if cfg!(target_endian = "big") {
+            T::from_le(positions.to_mut());
+        }
+
+        Ok(positions)
+    }
+
+    pub fn map_batches<F: FnMut(&[T])>(self, batch_size: usize, mut f: F) -> io::Result<()> {
+        for batch in self.positions()?.chunks(batch_size) {
+            f(batch);
+        }
+
+        Ok(())
+    }
+
+    pub fn map_positions<F: FnMut(&T)>(self, mut f: F) -> io::Result<()> {
+        for pos in self.positions()?.iter() {
+            f(pos);
+        }
+
+        Ok(())
+    }
+}
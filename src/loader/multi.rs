@@ -0,0 +1,180 @@
+use std::{fs::File, io, marker::PhantomData, path::Path};
+
+use super::{Layout, RecordReader, Shard};
+use crate::{
+    util::{self, Rand},
+    BulletFormat,
+};
+
+struct Source<T> {
+    reader: RecordReader,
+    weight: f64,
+    buffer: Vec<T>,
+    filled: usize,
+    pos: usize,
+}
+
+impl<T: BulletFormat> Source<T> {
+    fn next(&mut self) -> io::Result<Option<T>> {
+        if self.pos == self.filled {
+            self.filled = self.reader.read(&mut self.buffer)?;
+            self.pos = 0;
+
+            if self.filled == 0 {
+                return Ok(None);
+            }
+        }
+
+        self.pos += 1;
+        Ok(Some(self.buffer[self.pos - 1]))
+    }
+}
+
+/// Interleaves positions from several files, picking the source of each
+/// position at random in proportion to its weight. Once a source is
+/// exhausted, the remaining sources continue in proportion to their weights.
+///
+/// Weights must be finite and non-negative, and sources with weight 0 are
+/// skipped entirely.
+pub struct MultiDataLoader<T> {
+    files: Vec<(File, Layout, f64)>,
+    buffer_size: usize,
+    seed: u64,
+    skip_partial: bool,
+    shard: Shard,
+    marker: PhantomData<T>,
+}
+
+impl<T: BulletFormat> MultiDataLoader<T> {
+    const DATA_SIZE: usize = std::mem::size_of::<T>();
+
+    pub fn new<P: AsRef<Path>>(sources: &[(P, f64)], buffer_size_mb: usize) -> io::Result<Self> {
+        if let Some((_, weight)) = sources
+            .iter()
+            .find(|(_, weight)| !weight.is_finite() || *weight < 0.0)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("source weight {weight} is not a finite non-negative number"),
+            ));
+        }
+
+        let files = sources
+            .iter()
+            .map(|(path, weight)| {
+                let file = File::open(path)?;
+                let layout = Layout::read::<T>(&file)?;
+                Ok((file, layout, *weight))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            files,
+            buffer_size: buffer_size_mb * 1024 * 1024,
+            seed: 0,
+            skip_partial: false,
+            shard: Shard::default(),
+            marker: PhantomData,
+        })
+    }
+
+    /// Shards each file as in [`DataLoader::shard`](super::DataLoader::shard).
+    pub fn shard(mut self, index: usize, count: usize) -> Self {
+        self.shard = Shard::new(index, count);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// See [`DataLoader::skip_trailing_partial`](super::DataLoader::skip_trailing_partial).
+    pub fn skip_trailing_partial(mut self) -> Self {
+        self.skip_partial = true;
+        self
+    }
+
+    /// Total of [`DataLoader::skipped_bytes`](super::DataLoader::skipped_bytes)
+    /// over all files.
+    pub fn skipped_bytes(&self) -> u64 {
+        self.files
+            .iter()
+            .map(|(_, layout, _)| layout.skipped_bytes(self.skip_partial))
+            .sum()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files
+            .iter()
+            .filter(|(_, _, weight)| *weight > 0.0)
+            .map(|(_, layout, _)| self.shard.range(layout.len::<T>()).len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn map_batches<F: FnMut(&[T])>(self, batch_size: usize, mut f: F) -> io::Result<()> {
+        let per_source = self.buffer_size / self.files.len().max(1);
+        let cap = (per_source / Self::DATA_SIZE).max(1);
+
+        let mut sources = self
+            .files
+            .into_iter()
+            .filter(|(_, _, weight)| *weight > 0.0)
+            .map(|(file, layout, weight)| {
+                Ok(Source {
+                    reader: RecordReader::new::<T>(file, layout, self.skip_partial, self.shard)?,
+                    weight,
+                    buffer: util::zeroed_vec(cap),
+                    filled: 0,
+                    pos: 0,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut rng = Rand::new(self.seed);
+        let mut batch = Vec::with_capacity(batch_size);
+        let mut total_weight = sources.iter().map(|source| source.weight).sum::<f64>();
+
+        while !sources.is_empty() {
+            let mut pick = rng.rand_f64() * total_weight;
+            let mut idx = sources.len() - 1;
+            for (i, source) in sources.iter().enumerate() {
+                if pick < source.weight {
+                    idx = i;
+                    break;
+                }
+
+                pick -= source.weight;
+            }
+
+            if let Some(pos) = sources[idx].next()? {
+                batch.push(pos);
+
+                if batch.len() == batch_size {
+                    f(&batch);
+                    batch.clear();
+                }
+            } else {
+                total_weight -= sources.swap_remove(idx).weight;
+            }
+        }
+
+        if !batch.is_empty() {
+            f(&batch);
+        }
+
+        Ok(())
+    }
+
+    pub fn map_positions<F: FnMut(&T)>(self, mut f: F) -> io::Result<()> {
+        self.map_batches(16_384, |batch| {
+            for pos in batch {
+                f(pos);
+            }
+        })
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+/// Receives updates from long-running operations.
+pub trait Progress {
+    /// Called periodically with the number of positions (or lines) processed
+    /// so far, out of `total` if it is known in advance.
+    fn update(&mut self, done: usize, total: Option<usize>);
+
+    /// Called once when the operation completes.
+    fn finish(&mut self) {}
+}
+
+/// Ignores all updates.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn update(&mut self, _: usize, _: Option<usize>) {}
+}
+
+/// Prints a single updating line to stderr with throughput and, if the
+/// total is known, an ETA.
+#[derive(Clone, Debug)]
+pub struct TerminalProgress {
+    label: String,
+    start: Instant,
+    last_print: Option<Instant>,
+    line: String,
+}
+
+impl Default for TerminalProgress {
+    fn default() -> Self {
+        Self::new("Processed")
+    }
+}
+
+impl TerminalProgress {
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn new(label: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            start: Instant::now(),
+            last_print: None,
+            line: String::new(),
+        }
+    }
+}
+
+impl Progress for TerminalProgress {
+    fn update(&mut self, done: usize, total: Option<usize>) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        let per_sec = done as f64 / elapsed.max(1e-9);
+
+        self.line = match total {
+            Some(total) => {
+                let eta = total.saturating_sub(done) as f64 / per_sec.max(1e-9);
+                format!(
+                    "> {} {done} / {total} ({:.1}%), {per_sec:.0}/s, ETA {eta:.0}s",
+                    self.label,
+                    100.0 * done as f64 / total.max(1) as f64,
+                )
+            }
+            None => format!("> {} {done}, {per_sec:.0}/s", self.label),
+        };
+
+        if self
+            .last_print
+            .is_none_or(|last| now - last >= Self::INTERVAL)
+        {
+            self.last_print = Some(now);
+            let mut stderr = io::stderr().lock();
+            let _ = write!(stderr, "\r{}", self.line);
+            let _ = stderr.flush();
+        }
+    }
+
+    fn finish(&mut self) {
+        if !self.line.is_empty() {
+            eprintln!(
+                "\r{}, took {:.1}s",
+                self.line,
+                self.start.elapsed().as_secs_f64()
+            );
+        }
+    }
+}
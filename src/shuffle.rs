@@ -0,0 +1,144 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    util::{self, Rand, Reporter},
+    BulletFormat, DataLoader, Progress,
+};
+
+/// Shuffles a file of any size with bounded memory usage.
+///
+/// Positions are first scattered uniformly at random into temporary bucket
+/// files alongside `out_path`, then each bucket is shuffled in memory and
+/// appended to the output. Buckets that are still too large for `memory_mb`
+/// are scattered again in turn. The result is reproducible for a given `seed`.
+///
+/// Progress is reported over the first scatter and the final writes, so
+/// counts up to twice the number of positions.
+pub fn shuffle_file<T: BulletFormat>(
+    inp_path: impl AsRef<Path>,
+    out_path: impl AsRef<Path>,
+    memory_mb: usize,
+    seed: u64,
+    progress: &mut impl Progress,
+) -> io::Result<()> {
+    let (budget, load_mb) = util::memory_budget::<T>(memory_mb);
+
+    let loader = DataLoader::<T>::new(&inp_path, load_mb)?;
+    let headers = util::read_header::<T>(&inp_path)?;
+    let mut output = BufWriter::new(File::create(&out_path)?);
+    output.write_all(&headers)?;
+
+    let mut shuffler = Shuffler {
+        budget,
+        load_mb,
+        rng: Rand::new(seed),
+        reporter: Reporter::new(progress, 2 * loader.len()),
+    };
+
+    shuffler.shuffle(loader, out_path.as_ref(), &mut output, true)?;
+    shuffler.reporter.finish();
+
+    output.flush()
+}
+
+struct Shuffler<'a, P> {
+    budget: usize,
+    load_mb: usize,
+    rng: Rand,
+    reporter: Reporter<'a, P>,
+}
+
+impl<P: Progress> Shuffler<'_, P> {
+    /// Shuffles the positions from `loader` into `output`, scattering them
+    /// into buckets named after `temp_base` if they do not fit in memory.
+    fn shuffle<T: BulletFormat>(
+        &mut self,
+        loader: DataLoader<T>,
+        temp_base: &Path,
+        output: &mut impl Write,
+        top: bool,
+    ) -> io::Result<()> {
+        let data_size = std::mem::size_of::<T>();
+        let len = loader.len();
+
+        if len * data_size <= self.budget {
+            let mut data = loader.read_range(0..len)?;
+            if top {
+                self.reporter.report(len);
+            }
+
+            self.rng.shuffle(&mut data);
+            BulletFormat::write_to_bin(output, &data)?;
+            self.reporter.report(len);
+
+            return Ok(());
+        }
+
+        // aim for half the budget per bucket, as bucket sizes vary
+        let num_buckets = (len * data_size)
+            .div_ceil(self.budget / 2)
+            .clamp(2, util::MAX_TEMP_FILES);
+
+        let rng = &mut self.rng;
+        let buckets = util::scatter(
+            loader,
+            temp_base,
+            num_buckets,
+            self.budget,
+            top.then_some(&mut self.reporter),
+            |_| rng.rand_int(num_buckets),
+        )?;
+
+        for path in buckets.paths() {
+            let bucket = DataLoader::<T>::headerless(path, self.load_mb)?;
+            self.shuffle(bucket, path, output, false)?;
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::shuffle_file;
+    use crate::{util, ChessBoard, DataLoader, NoProgress};
+
+    #[test]
+    fn recursive_buckets() {
+        let inp = util::write_test_file("shuffle-inp", None, &util::test_positions(0..100_000));
+        let out = util::test_path("shuffle-out");
+
+        // the smallest budget, so buckets have to be scattered again
+        shuffle_file::<ChessBoard>(&inp, &out, 0, 7, &mut NoProgress).unwrap();
+
+        let mut shuffled = Vec::new();
+        DataLoader::<ChessBoard>::new(&out, 1)
+            .unwrap()
+            .map_positions(|pos| shuffled.push(pos.occ))
+            .unwrap();
+
+        assert_ne!(shuffled, (0..100_000).collect::<Vec<_>>());
+        shuffled.sort_unstable();
+        assert_eq!(shuffled, (0..100_000).collect::<Vec<_>>());
+
+        let leftover = fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                name.to_string_lossy()
+                    .starts_with("bulletformat-shuffle-out.bin.")
+            })
+            .count();
+        assert_eq!(leftover, 0);
+
+        fs::remove_file(inp).unwrap();
+        fs::remove_file(out).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    header,
+    util::{self, Rand},
+    BulletFormat, DataLoader, Progress,
+};
+
+/// Size of the validation set.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitSize {
+    /// Fraction of the positions in the file.
+    Ratio(f64),
+    /// Number of positions, capped at the number in the file.
+    Count(usize),
+}
+
+/// How positions are chosen for the validation set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitMethod {
+    /// Uniformly at random, reproducibly for a given seed.
+    Random { seed: u64 },
+    /// The positions at the end of the file.
+    Tail,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SplitSummary {
+    pub train: usize,
+    pub valid: usize,
+}
+
+/// Splits a file into training and validation files in a single streaming
+/// pass, preserving the order of positions within each output.
+pub fn split_file<T: BulletFormat>(
+    inp_path: impl AsRef<Path>,
+    train_path: impl AsRef<Path>,
+    valid_path: impl AsRef<Path>,
+    size: SplitSize,
+    method: SplitMethod,
+    progress: &mut impl Progress,
+) -> io::Result<SplitSummary> {
+    let loader = DataLoader::<T>::new(&inp_path, 512)?;
+    let total = loader.len();
+
+    let num_valid = match size {
+        SplitSize::Ratio(ratio) => (ratio.clamp(0.0, 1.0) * total as f64).round() as usize,
+        SplitSize::Count(count) => count.min(total),
+    };
+
+    let headers = util::read_header::<T>(&inp_path)?;
+    let mut train = BufWriter::new(File::create(train_path)?);
+    let mut valid = BufWriter::new(File::create(valid_path)?);
+    train.write_all(&header::with_count(&headers, total - num_valid))?;
+    valid.write_all(&header::with_count(&headers, num_valid))?;
+
+    let mut rng = match method {
+        SplitMethod::Random { seed } => Some(Rand::new(seed)),
+        SplitMethod::Tail => None,
+    };
+
+    let batch_size = loader.max_batch_size();
+    let mut train_buffer = Vec::with_capacity(batch_size);
+    let mut valid_buffer = Vec::with_capacity(batch_size);
+    let mut seen = 0;
+    let mut summary = SplitSummary::default();
+    let mut result = Ok(());
+
+    loader.map_batches(batch_size, |batch| {
+        if result.is_err() {
+            return;
+        }
+
+        for &pos in batch {
+            let remaining = total - seen;
+            let needed = num_valid - summary.valid;
+
+            // selection sampling, so exactly `num_valid` positions are chosen
+            let is_valid = match &mut rng {
+                Some(rng) => rng.rand_int(remaining) < needed,
+                None => remaining <= needed,
+            };
+
+            if is_valid {
+                valid_buffer.push(pos);
+                summary.valid += 1;
+            } else {
+                train_buffer.push(pos);
+                summary.train += 1;
+            }
+
+            seen += 1;
+        }
+
+        result = BulletFormat::write_to_bin(&mut train, &train_buffer)
+            .and_then(|_| BulletFormat::write_to_bin(&mut valid, &valid_buffer));
+        train_buffer.clear();
+        valid_buffer.clear();
+
+        progress.update(seen, Some(total));
+    })?;
+
+    progress.finish();
+
+    result?;
+    train.flush()?;
+    valid.flush()?;
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::{util, ChessBoard, NoProgress};
+
+    #[test]
+    fn exact_sizes() {
+        let inp = util::write_test_file("split-inp", None, &util::test_positions(0..10_000));
+        let train = util::test_path("split-train");
+        let valid = util::test_path("split-valid");
+
+        let scores = |path| {
+            let mut scores = Vec::new();
+            DataLoader::<ChessBoard>::new(path, 1)
+                .unwrap()
+                .map_positions(|pos| scores.push(pos.score))
+                .unwrap();
+            scores
+        };
+
+        let cases = [
+            (
+                SplitSize::Ratio(0.1),
+                SplitMethod::Random { seed: 1 },
+                1_000,
+            ),
+            (
+                SplitSize::Count(1_234),
+                SplitMethod::Random { seed: 2 },
+                1_234,
+            ),
+            (
+                SplitSize::Count(20_000),
+                SplitMethod::Random { seed: 3 },
+                10_000,
+            ),
+            (SplitSize::Ratio(0.25), SplitMethod::Tail, 2_500),
+        ];
+
+        for (size, method, num_valid) in cases {
+            let summary =
+                split_file::<ChessBoard>(&inp, &train, &valid, size, method, &mut NoProgress)
+                    .unwrap();
+            assert_eq!(summary.valid, num_valid);
+            assert_eq!(summary.train, 10_000 - num_valid);
+
+            let (train_scores, valid_scores) = (scores(&train), scores(&valid));
+            assert_eq!(valid_scores.len(), num_valid);
+            assert!(train_scores.is_sorted() && valid_scores.is_sorted());
+
+            let mut all = [train_scores, valid_scores.clone()].concat();
+            all.sort_unstable();
+            assert!(all.into_iter().eq(0..10_000));
+
+            if method == SplitMethod::Tail {
+                assert!(valid_scores.into_iter().eq(7_500..10_000));
+            }
+        }
+
+        for path in [inp, train, valid] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use std::{collections::BTreeMap, fmt, io, path::Path};
+
+use crate::{BulletFormat, ChessBoard, DataLoader, Progress};
+
+/// Summary of the scores and results in a dataset.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    pub count: usize,
+    /// Positions by `result_idx`, i.e. losses, draws and wins.
+    pub wdl: [usize; 3],
+    /// Width of the buckets in `score_histogram`.
+    pub bucket_width: i32,
+    /// Positions by score, keyed by the lowest score in each bucket.
+    pub score_histogram: BTreeMap<i32, usize>,
+    score_sum: f64,
+    score_sq_sum: f64,
+}
+
+impl Stats {
+    pub fn new(bucket_width: i32) -> Self {
+        assert!(bucket_width > 0, "Bucket width must be positive!");
+
+        Self {
+            count: 0,
+            wdl: [0; 3],
+            bucket_width,
+            score_histogram: BTreeMap::new(),
+            score_sum: 0.0,
+            score_sq_sum: 0.0,
+        }
+    }
+
+    pub fn push<T: BulletFormat>(&mut self, pos: &T) {
+        let score = i32::from(pos.score());
+
+        self.count += 1;
+        self.wdl[pos.result_idx().min(2)] += 1;
+        *self
+            .score_histogram
+            .entry(score.div_euclid(self.bucket_width) * self.bucket_width)
+            .or_default() += 1;
+        self.score_sum += f64::from(score);
+        self.score_sq_sum += f64::from(score).powi(2);
+    }
+
+    pub fn mean_score(&self) -> f64 {
+        self.score_sum / self.count.max(1) as f64
+    }
+
+    pub fn score_stddev(&self) -> f64 {
+        let mean = self.mean_score();
+        (self.score_sq_sum / self.count.max(1) as f64 - mean * mean)
+            .max(0.0)
+            .sqrt()
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pct = |x: usize| 100.0 * x as f64 / self.count.max(1) as f64;
+
+        writeln!(f, "Positions      : {}", self.count)?;
+        writeln!(
+            f,
+            "W / D / L      : {:.2}% / {:.2}% / {:.2}%",
+            pct(self.wdl[2]),
+            pct(self.wdl[1]),
+            pct(self.wdl[0]),
+        )?;
+        writeln!(f, "Score Mean     : {:.2}", self.mean_score())?;
+        writeln!(f, "Score Std Dev  : {:.2}", self.score_stddev())?;
+        writeln!(f, "Score Histogram:")?;
+
+        for (&start, &count) in &self.score_histogram {
+            let end = start + self.bucket_width - 1;
+            writeln!(f, "  {start:>6} to {end:>6} : {count} ({:.2}%)", pct(count))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Summary of the material in a dataset of [`ChessBoard`]s.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChessStats {
+    pub count: usize,
+    /// Positions by number of pieces, kings included.
+    pub piece_counts: [usize; 33],
+    /// Occurrences of each piece, in order `PNBRQKpnbrqk`, with upper case
+    /// being the side-to-move.
+    pub piece_types: [usize; 12],
+    /// Positions with more than 32 pieces or invalid piece codes, which are
+    /// left out of the counts above.
+    pub invalid: usize,
+}
+
+impl Default for ChessStats {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            piece_counts: [0; 33],
+            piece_types: [0; 12],
+            invalid: 0,
+        }
+    }
+}
+
+impl ChessStats {
+    pub fn push(&mut self, pos: &ChessBoard) {
+        self.count += 1;
+
+        // only 32 pieces fit in a position, so any more cannot be read
+        let pieces = pos.occ().count_ones() as usize;
+        if pieces > 32 || pos.into_iter().any(|(piece, _)| piece & 7 > 5) {
+            self.invalid += 1;
+            return;
+        }
+
+        self.piece_counts[pieces] += 1;
+
+        for (piece, _) in *pos {
+            self.piece_types[6 * usize::from(piece >> 3) + usize::from(piece & 7)] += 1;
+        }
+    }
+}
+
+impl fmt::Display for ChessStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = (self.count - self.invalid).max(1) as f64;
+
+        if self.invalid > 0 {
+            writeln!(f, "Invalid Positions: {}", self.invalid)?;
+        }
+
+        writeln!(f, "Piece Count Histogram:")?;
+        for (pieces, &num) in self.piece_counts.iter().enumerate().filter(|x| *x.1 > 0) {
+            writeln!(
+                f,
+                "  {pieces:>2} : {num} ({:.2}%)",
+                100.0 * num as f64 / count
+            )?;
+        }
+
+        writeln!(f, "Mean Pieces per Position:")?;
+        for (piece, &num) in "PNBRQKpnbrqk".chars().zip(self.piece_types.iter()) {
+            writeln!(f, "  {piece} : {:.3}", num as f64 / count)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Gathers [`Stats`] over a file in a single pass.
+pub fn dataset_stats<T: BulletFormat>(
+    path: impl AsRef<Path>,
+    bucket_width: i32,
+    progress: &mut impl Progress,
+) -> io::Result<Stats> {
+    let mut stats = Stats::new(bucket_width);
+    let loader = DataLoader::<T>::new(path, 512)?;
+    let total = loader.len();
+
+    loader.map_batches(16_384, |batch| {
+        for pos in batch {
+            stats.push(pos);
+        }
+
+        progress.update(stats.count, Some(total));
+    })?;
+
+    progress.finish();
+
+    Ok(stats)
+}
+
+/// Gathers [`Stats`] and [`ChessStats`] over a file in a single pass.
+pub fn chess_dataset_stats(
+    path: impl AsRef<Path>,
+    bucket_width: i32,
+    progress: &mut impl Progress,
+) -> io::Result<(Stats, ChessStats)> {
+    let mut stats = Stats::new(bucket_width);
+    let mut chess_stats = ChessStats::default();
+    let loader = DataLoader::<ChessBoard>::new(path, 512)?;
+    let total = loader.len();
+
+    loader.map_batches(16_384, |batch| {
+        for pos in batch {
+            stats.push(pos);
+            chess_stats.push(pos);
+        }
+
+        progress.update(stats.count, Some(total));
+    })?;
+
+    progress.finish();
+
+    Ok((stats, chess_stats))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::{util, NoProgress};
+
+    #[test]
+    fn scores_and_results() {
+        let mut stats = Stats::new(100);
+
+        for (score, result) in [(-101, 0), (-1, 1), (0, 1), (99, 2), (203, 3)] {
+            stats.push(&ChessBoard {
+                score,
+                result,
+                ..Default::default()
+            });
+        }
+
+        assert_eq!(stats.count, 5);
+        assert_eq!(stats.wdl, [1, 2, 2]);
+
+        let histogram = stats
+            .score_histogram
+            .clone()
+            .into_iter()
+            .collect::<Vec<_>>();
+        assert_eq!(histogram, [(-200, 1), (-100, 1), (0, 2), (200, 1)]);
+
+        assert!((stats.mean_score() - 40.0).abs() < 1e-9);
+        assert!((stats.score_stddev() - 10_642.4f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn corrupt_pieces() {
+        let startpos = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1 | 0 | 0.5";
+        let corrupt = [
+            ChessBoard {
+                occ: u64::MAX,
+                ..Default::default()
+            },
+            ChessBoard {
+                occ: 0b11,
+                pcs: [0x60, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                ..Default::default()
+            },
+        ];
+
+        let data = [startpos.parse().unwrap(), corrupt[0], corrupt[1]];
+        let path = util::write_test_file("stats", None, &data);
+
+        let (stats, chess_stats) = chess_dataset_stats(&path, 100, &mut NoProgress).unwrap();
+        assert_eq!(stats.count, 3);
+        assert_eq!(chess_stats.count, 3);
+        assert_eq!(chess_stats.invalid, 2);
+        assert_eq!(chess_stats.piece_counts.iter().sum::<usize>(), 1);
+        assert_eq!(chess_stats.piece_counts[32], 1);
+        assert_eq!(
+            chess_stats.piece_types,
+            [8, 2, 2, 2, 1, 1, 8, 2, 2, 2, 1, 1]
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,16 +1,381 @@
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{loader::Layout, BulletFormat, DataLoader, Progress};
+
+#[cfg(test)]
+use crate::{ChessBoard, FileHeader};
+
 pub fn sigmoid(x: f32, k: f32) -> f32 {
     1. / (1. + (-x * k).exp())
 }
 
-pub fn to_slice_with_lifetime<T, U>(slice: &[T]) -> &[U] {
-    let src_size = std::mem::size_of_val(slice);
-    let tgt_size = std::mem::size_of::<U>();
+/// Path for a temporary file stored alongside `path`.
+pub fn temp_path(path: &Path, name: &str, idx: usize) -> PathBuf {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{name}{idx}"));
+    PathBuf::from(temp)
+}
+
+/// Upper bound on the number of temporary files open at once.
+pub const MAX_TEMP_FILES: usize = 128;
+
+/// Memory budget in bytes for `memory_mb`, raised so that every temporary
+/// file gets a buffer, along with the loader buffer size in MB within it.
+pub fn memory_budget<T>(memory_mb: usize) -> (usize, usize) {
+    let budget = (memory_mb * 1024 * 1024).max(4 * MAX_TEMP_FILES * std::mem::size_of::<T>());
+    let load_mb = (budget / 4 / (1024 * 1024)).max(1);
+    (budget, load_mb)
+}
+
+/// Progress over passes through a known number of positions.
+pub struct Reporter<'a, P> {
+    progress: &'a mut P,
+    done: usize,
+    total: usize,
+}
+
+impl<'a, P: Progress> Reporter<'a, P> {
+    pub fn new(progress: &'a mut P, total: usize) -> Self {
+        Self {
+            progress,
+            done: 0,
+            total,
+        }
+    }
+
+    pub fn report(&mut self, count: usize) {
+        self.done += count;
+        self.progress.update(self.done, Some(self.total));
+    }
+
+    pub fn finish(&mut self) {
+        self.progress.finish();
+    }
+}
+
+/// Temporary files, removed when dropped if still present.
+pub struct TempFiles(Vec<PathBuf>);
+
+impl TempFiles {
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.0
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Scatters the positions from `loader` into `count` headerless temporary
+/// files alongside `temp_base`, with `pick` choosing the file for each.
+///
+/// Write buffers are limited to a quarter of `budget`, and each batch read
+/// is counted by `reporter`, if given.
+pub fn scatter<T: BulletFormat, P: Progress>(
+    loader: DataLoader<T>,
+    temp_base: &Path,
+    count: usize,
+    budget: usize,
+    mut reporter: Option<&mut Reporter<P>>,
+    mut pick: impl FnMut(&T) -> usize,
+) -> io::Result<TempFiles> {
+    let files = TempFiles(
+        (0..count)
+            .map(|i| temp_path(temp_base, "part", i))
+            .collect(),
+    );
+
+    let data_size = std::mem::size_of::<T>();
+    let buffer_len = (budget / 4 / count / data_size).clamp(1, 1024);
+
+    let mut writers = files
+        .paths()
+        .iter()
+        .map(|path| Ok((BufWriter::new(File::create(path)?), Vec::new())))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut result = Ok(());
+    let batch_size = loader.max_batch_size();
+
+    loader.map_batches(batch_size, |batch| {
+        if result.is_err() {
+            return;
+        }
+
+        if let Some(reporter) = &mut reporter {
+            reporter.report(batch.len());
+        }
+
+        for pos in batch {
+            let (writer, buffer) = &mut writers[pick(pos)];
+            buffer.push(*pos);
+
+            if buffer.len() == buffer_len {
+                if let Err(error) = BulletFormat::write_to_bin(writer, buffer) {
+                    result = Err(error);
+                    return;
+                }
+
+                buffer.clear();
+            }
+        }
+    })?;
+
+    result?;
+
+    for (mut writer, buffer) in writers {
+        BulletFormat::write_to_bin(&mut writer, &buffer)?;
+        writer.flush()?;
+    }
+
+    Ok(files)
+}
+
+/// Reads exactly enough bytes to fill `buf` from `offset`, without moving
+/// the cursor of `file`.
+#[cfg(unix)]
+pub fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+/// Reads exactly enough bytes to fill `buf` from `offset`. Unlike on unix,
+/// this moves the cursor of `file`, but never depends on it.
+#[cfg(windows)]
+pub fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                ))
+            }
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads exactly enough bytes to fill `buf` from `offset`. Without positional
+/// reads this has to seek, so calls are serialised to keep them from moving
+/// the cursor under each other.
+#[cfg(not(any(unix, windows)))]
+pub fn read_exact_at(mut file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    let _guard = LOCK
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+/// Reads all bytes preceding the positions, including any file header.
+pub fn read_header<T: BulletFormat>(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let layout = Layout::read::<T>(&file)?;
+    let mut header = vec![0; layout.header_size()];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header)?;
+    Ok(header)
+}
+
+/// Reads until `buf` is full or the reader is exhausted, returning the
+/// number of bytes read.
+pub fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(filled)
+}
+
+/// Types for which every bit pattern is a valid value, so that they can be
+/// freely reinterpreted as bytes and read back from them.
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]`, with no padding and with every field
+/// itself being an integer or an array of integers. The formats in this crate
+/// have fields adding up to 32 bytes, so asserting that their size is 32
+/// bytes also rules out padding.
+pub unsafe trait Pod: Copy + 'static {}
+
+pub fn bytes_of<T: Pod>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr().cast(), std::mem::size_of_val(data)) }
+}
+
+pub fn bytes_of_mut<T: Pod>(data: &mut [T]) -> &mut [u8] {
+    let len = std::mem::size_of_val(data);
+    unsafe { std::slice::from_raw_parts_mut(data.as_mut_ptr().cast(), len) }
+}
+
+/// Reinterprets `bytes` as records, or `None` if they are misaligned or not
+/// a whole number of records.
+pub fn try_cast_slice<T: Pod>(bytes: &[u8]) -> Option<&[T]> {
+    let size = std::mem::size_of::<T>();
+    let aligned = bytes.as_ptr().align_offset(std::mem::align_of::<T>()) == 0;
 
+    if !aligned || !bytes.len().is_multiple_of(size) {
+        return None;
+    }
+
+    Some(unsafe { std::slice::from_raw_parts(bytes.as_ptr().cast(), bytes.len() / size) })
+}
+
+/// As [`try_cast_slice`], but copying misaligned bytes into a new buffer.
+pub fn cast_slice_or_copy<T: Pod>(bytes: &[u8]) -> Cow<'_, [T]> {
+    if let Some(data) = try_cast_slice(bytes) {
+        return Cow::Borrowed(data);
+    }
+
+    let size = std::mem::size_of::<T>();
     assert!(
-        src_size % tgt_size == 0,
-        "Target type size does not divide slice size!"
+        bytes.len().is_multiple_of(size),
+        "Record size does not divide slice size!"
     );
 
-    let len = src_size / tgt_size;
-    unsafe { std::slice::from_raw_parts(slice.as_ptr().cast(), len) }
+    let mut data = zeroed_vec(bytes.len() / size);
+    bytes_of_mut(&mut data).copy_from_slice(bytes);
+    Cow::Owned(data)
+}
+
+/// Allocated as zeroed memory, so that large buffers are not written to
+/// up front.
+pub fn zeroed_vec<T: Pod>(len: usize) -> Vec<T> {
+    let layout = std::alloc::Layout::array::<T>(len).expect("Buffer too large!");
+
+    if layout.size() == 0 {
+        return Vec::new();
+    }
+
+    unsafe {
+        let ptr = std::alloc::alloc_zeroed(layout);
+        if ptr.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+
+        Vec::from_raw_parts(ptr.cast(), len, len)
+    }
+}
+
+/// SplitMix64, so that any seed (including 0) is valid.
+#[derive(Clone, Copy, Debug)]
+pub struct Rand(u64);
+
+impl Rand {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn state(&self) -> u64 {
+        self.0
+    }
+
+    pub fn rand(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..max`.
+    pub fn rand_int(&mut self, max: usize) -> usize {
+        ((u128::from(self.rand()) * max as u128) >> 64) as usize
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn rand_f64(&mut self) -> f64 {
+        (self.rand() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn shuffle<T>(&mut self, data: &mut [T]) {
+        for i in (1..data.len()).rev() {
+            let j = self.rand_int(i + 1);
+            data.swap(i, j);
+        }
+    }
+}
+
+/// Path of a temporary file for tests, which they remove once finished.
+#[cfg(test)]
+pub fn test_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("bulletformat-{name}.bin"))
+}
+
+/// Positions with `occ` and `score` set to their index, the latter wrapping.
+#[cfg(test)]
+pub fn test_positions(range: std::ops::Range<usize>) -> Vec<ChessBoard> {
+    range
+        .map(|i| ChessBoard {
+            occ: i as u64,
+            score: i as i16,
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Writes `positions` to [`test_path`], preceded by `header` if given.
+#[cfg(test)]
+pub fn write_test_file(
+    name: &str,
+    header: Option<FileHeader>,
+    positions: &[ChessBoard],
+) -> PathBuf {
+    let path = test_path(name);
+    let mut file = File::create(&path).unwrap();
+
+    if let Some(header) = header {
+        header.write(&mut file).unwrap();
+    }
+
+    ChessBoard::write_to_bin(&mut file, positions).unwrap();
+    path
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ChessBoard;
+
+    #[test]
+    fn misaligned_cast() {
+        let data = test_positions(0..4);
+
+        // aligned storage, so that an offset of one byte is misaligned
+        let mut storage = zeroed_vec::<ChessBoard>(5);
+        let bytes = &mut bytes_of_mut(&mut storage)[1..129];
+        bytes.copy_from_slice(bytes_of(&data));
+
+        assert!(try_cast_slice::<ChessBoard>(bytes).is_none());
+
+        let copied = cast_slice_or_copy::<ChessBoard>(bytes);
+        assert!(matches!(copied, Cow::Owned(_)));
+        assert_eq!(*copied, data[..]);
+    }
 }